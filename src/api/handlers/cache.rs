@@ -0,0 +1,28 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    Json,
+};
+use crate::AppState;
+use crate::api::types::{ApiResponse, CachePurgeRequest};
+
+pub async fn purge(
+    State(state): State<AppState>,
+    Json(request): Json<CachePurgeRequest>,
+) -> Result<Json<ApiResponse<String>>, StatusCode> {
+    if let Some(name) = &request.rule {
+        let config = state.config.read().await;
+        match config.forwarding_rules.iter().find(|rule| &rule.name == name) {
+            None => return Ok(Json(ApiResponse::error(format!("Rule '{}' not found", name)))),
+            Some(rule) if rule.cache.is_none() => {
+                return Ok(Json(ApiResponse::error(format!("Rule '{}' has no response cache", name))));
+            }
+            Some(_) => {}
+        }
+    }
+
+    match state.proxy_engine.purge_response_cache(request.rule.as_deref(), request.url_prefix.as_deref()).await {
+        Ok(purged) => Ok(Json(ApiResponse::success(format!("Purged {} cached responses", purged)))),
+        Err(e) => Ok(Json(ApiResponse::error(format!("Failed to purge response cache: {}", e)))),
+    }
+}
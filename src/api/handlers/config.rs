@@ -4,19 +4,23 @@ use axum::{
     Json,
 };
 use crate::{AppState, config::Config};
-use crate::api::types::{ApiResponse, ConfigValidationResult};
+use crate::api::types::{ApiResponse, ConfigValidationResult, WebSocketEvent};
 
 pub async fn get_config(
     State(state): State<AppState>,
 ) -> Result<Json<ApiResponse<Config>>, StatusCode> {
     let config = state.config.read().await;
-    Ok(Json(ApiResponse::success(config.clone())))
+    Ok(Json(ApiResponse::success(config.redacted())))
 }
 
 pub async fn update_config(
     State(state): State<AppState>,
-    Json(new_config): Json<Config>,
+    Json(mut new_config): Json<Config>,
 ) -> Result<Json<ApiResponse<String>>, StatusCode> {
+    if let Err(e) = new_config.restore_secrets(&*state.config.read().await) {
+        return Ok(Json(ApiResponse::error(format!("Invalid configuration: {}", e))));
+    }
+
     match new_config.validate() {
         Ok(_) => {
             if let Err(e) = state.proxy_engine.update_rules(new_config.forwarding_rules.clone()).await {
@@ -25,6 +29,7 @@ pub async fn update_config(
             
             let mut config = state.config.write().await;
             *config = new_config;
+            let _ = state.events.send(WebSocketEvent::config_changed(&config));
             
             Ok(Json(ApiResponse::success("Configuration updated successfully".to_string())))
         }
@@ -49,6 +54,7 @@ pub async fn reload_config(
                     
                     let mut config = state.config.write().await;
                     *config = new_config;
+                    let _ = state.events.send(WebSocketEvent::config_changed(&config));
                     
                     Ok(Json(ApiResponse::success("Configuration reloaded successfully".to_string())))
                 }
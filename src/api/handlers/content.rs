@@ -1,10 +1,14 @@
 use axum::{
-    extract::State,
+    extract::{Path, State},
     http::StatusCode,
     Json,
 };
 use crate::AppState;
-use crate::api::types::{ApiResponse, ContentSourceInfo, CacheStats};
+use crate::config::{ContentSource, ForwardingRule};
+use crate::content::secret::fingerprint;
+use crate::util::iso8601;
+use crate::api::types::{ApiResponse, ContentPreview, ContentSourceInfo, CacheStats, ValueUsageInfo};
+use std::time::UNIX_EPOCH;
 
 pub async fn list_sources(
     State(state): State<AppState>,
@@ -13,23 +17,21 @@ pub async fn list_sources(
     let mut sources = Vec::new();
     
     for rule in &config.forwarding_rules {
-        for (key, source) in &rule.header_replacements {
+        for (identifier, source) in rule_sources(rule) {
+            let info = state.proxy_engine.content_source_info(source).await;
             sources.push(ContentSourceInfo {
                 source_type: format!("{:?}", source.source),
-                identifier: format!("{}:header:{}", rule.name, key),
-                last_updated: None,
+                identifier,
+                last_updated: info.as_ref().map(|info| {
+                    let secs = info.inserted_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                    iso8601(secs)
+                }),
                 cache_ttl: source.cache_ttl,
-                content_count: 0, // TODO: Get actual count from content manager
-            });
-        }
-        
-        for (key, source) in &rule.body_replacements {
-            sources.push(ContentSourceInfo {
-                source_type: format!("{:?}", source.source),
-                identifier: format!("{}:body:{}", rule.name, key),
-                last_updated: None,
-                cache_ttl: source.cache_ttl,
-                content_count: 0, // TODO: Get actual count from content manager
+                content_count: info.as_ref().map_or(0, |info| info.value_count),
+                size_bytes: info.as_ref().map_or(0, |info| info.size_bytes),
+                hit_count: info.as_ref().map_or(0, |info| info.hits),
+                fetch_duration_ms: info.as_ref().map(|info| info.fetch_duration.as_millis() as u64),
+                stale: info.as_ref().is_some_and(|info| info.stale),
             });
         }
     }
@@ -37,6 +39,91 @@ pub async fn list_sources(
     Ok(Json(ApiResponse::success(sources)))
 }
 
+/// Every content source of a rule, with the identifier used by the API.
+fn rule_sources(rule: &ForwardingRule) -> Vec<(String, &ContentSource)> {
+    let mut sources = Vec::new();
+
+    for (key, source) in &rule.header_replacements {
+        sources.push((format!("{}:header:{}", rule.name, key), source));
+    }
+    for (key, replacement) in &rule.body_replacements {
+        sources.push((format!("{}:body:{}", rule.name, key), &replacement.content));
+    }
+    for (key, replacement) in &rule.response_body_replacements {
+        sources.push((format!("{}:response_body:{}", rule.name, key), &replacement.content));
+    }
+    for (direction, operations) in [("request", &rule.request_headers), ("response", &rule.response_headers)] {
+        for operation in operations {
+            if let Some(source) = &operation.content {
+                sources.push((format!("{}:{}:{}", rule.name, direction, operation.name), source));
+            }
+        }
+    }
+
+    sources
+}
+
+pub async fn refresh_source(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ApiResponse<String>>, StatusCode> {
+    let Some(source) = find_source(&state, &id).await else {
+        return Ok(Json(ApiResponse::error(format!("Content source '{}' not found", id))));
+    };
+
+    match state.proxy_engine.refresh_content(&source).await {
+        Ok(content) => Ok(Json(ApiResponse::success(format!(
+            "Content source '{}' refreshed with {} values", id, content.len()
+        )))),
+        Err(e) => Ok(Json(ApiResponse::error(format!("Failed to refresh content source '{}': {}", id, e)))),
+    }
+}
+
+pub async fn invalidate_source(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ApiResponse<String>>, StatusCode> {
+    let Some(source) = find_source(&state, &id).await else {
+        return Ok(Json(ApiResponse::error(format!("Content source '{}' not found", id))));
+    };
+
+    state.proxy_engine.remove_content_from_cache(&source).await;
+    Ok(Json(ApiResponse::success(format!("Cache for content source '{}' cleared", id))))
+}
+
+pub async fn preview_source(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ApiResponse<ContentPreview>>, StatusCode> {
+    let Some(source) = find_source(&state, &id).await else {
+        return Ok(Json(ApiResponse::error(format!("Content source '{}' not found", id))));
+    };
+
+    match state.proxy_engine.preview_content(&source).await {
+        Ok(content) => Ok(Json(ApiResponse::success(ContentPreview {
+            identifier: id,
+            source_type: format!("{:?}", source.source),
+            value_count: content.len(),
+            values: if content.secret {
+                content.values.iter().map(|value| fingerprint(value)).collect()
+            } else {
+                content.values.iter().map(|value| value.to_string()).collect()
+            },
+            weights: content.weights.clone(),
+        }))),
+        Err(e) => Ok(Json(ApiResponse::error(format!("Failed to load content source '{}': {}", id, e)))),
+    }
+}
+
+/// Looks up a content source by the identifier reported by `list_sources`.
+async fn find_source(state: &AppState, id: &str) -> Option<ContentSource> {
+    let config = state.config.read().await;
+    config.forwarding_rules.iter()
+        .flat_map(rule_sources)
+        .find(|(identifier, _)| identifier == id)
+        .map(|(_, source)| source.clone())
+}
+
 pub async fn clear_cache(
     State(state): State<AppState>,
 ) -> Result<Json<ApiResponse<String>>, StatusCode> {
@@ -45,16 +132,41 @@ pub async fn clear_cache(
 }
 
 pub async fn cache_stats(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
 ) -> Result<Json<ApiResponse<CacheStats>>, StatusCode> {
-    // TODO: Implement actual cache statistics
+    let snapshot = state.proxy_engine.cache_stats().await;
+    let lookups = snapshot.hits + snapshot.misses;
     let stats = CacheStats {
-        total_entries: 0,
-        hit_count: 0,
-        miss_count: 0,
-        hit_ratio: 0.0,
-        memory_usage_bytes: 0,
+        total_entries: snapshot.entries,
+        hit_count: snapshot.hits,
+        miss_count: snapshot.misses,
+        hit_ratio: if lookups > 0 { snapshot.hits as f64 / lookups as f64 } else { 0.0 },
+        eviction_count: snapshot.evictions,
+        expired_count: snapshot.expirations,
+        memory_usage_bytes: snapshot.size_bytes,
+        max_entries: snapshot.max_entries,
+        max_bytes: snapshot.max_bytes,
     };
     
     Ok(Json(ApiResponse::success(stats)))
-}
\ No newline at end of file
+}
+
+pub async fn usage_stats(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<ValueUsageInfo>>>, StatusCode> {
+    let usage = state.proxy_engine.content_usage()
+        .into_iter()
+        .map(|usage| ValueUsageInfo {
+            key: usage.scope,
+            value: fingerprint(&usage.value),
+            window_count: usage.window_count,
+            day_count: usage.day_count,
+            total_count: usage.total_count,
+            exhausted: usage.exhausted_for.is_some(),
+            exhausted_for_seconds: usage.exhausted_for.map(|d| d.as_secs()),
+            last_status: usage.last_status,
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(usage)))
+}
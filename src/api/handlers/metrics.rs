@@ -5,7 +5,8 @@ use axum::{
 };
 use std::collections::HashMap;
 use crate::AppState;
-use crate::api::types::{ApiResponse, SystemMetrics, HealthStatus};
+use crate::api::types::{ApiResponse, BackendStatus, SystemMetrics, HealthStatus};
+use crate::proxy::circuit_breaker::CircuitState;
 
 pub async fn get_metrics(
     State(_state): State<AppState>,
@@ -44,6 +45,24 @@ pub async fn get_status(
 ) -> Result<Json<ApiResponse<serde_json::Value>>, StatusCode> {
     let config = state.config.read().await;
     let rules_count = config.forwarding_rules.len();
+
+    let circuits = state.proxy_engine.circuit_snapshot();
+    let mut backends = Vec::new();
+    for rule in &config.forwarding_rules {
+        for target in &rule.target_urls {
+            let circuit = circuits.iter().find(|circuit| circuit.rule == rule.name && &circuit.target == target);
+            backends.push(BackendStatus {
+                rule: rule.name.clone(),
+                target: target.clone(),
+                circuit_state: circuit.map_or(CircuitState::Closed, |circuit| circuit.state).as_str().to_string(),
+                consecutive_failures: circuit.map_or(0, |circuit| circuit.consecutive_failures),
+                failure_rate: circuit.map_or(0.0, |circuit| circuit.failure_rate),
+                retry_in_seconds: circuit.and_then(|circuit| circuit.retry_in).map(|retry_in| retry_in.as_secs_f64()),
+                in_flight: state.proxy_engine.target_in_flight(&rule.name, target),
+                response_time_ms: state.proxy_engine.target_latency_ms(&rule.name, target),
+            });
+        }
+    }
     
     let status = serde_json::json!({
         "proxy_status": "running",
@@ -53,7 +72,8 @@ pub async fn get_status(
             "port": config.server.port,
             "web_ui_port": config.server.web_ui_port
         },
-        "logging_level": config.logging.level
+        "logging_level": config.logging.level,
+        "backends": backends
     });
     
     Ok(Json(ApiResponse::success(status)))
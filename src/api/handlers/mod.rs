@@ -1,4 +1,6 @@
 pub mod config;
 pub mod rules;
 pub mod content;
+pub mod cache;
+pub mod rate_limits;
 pub mod metrics;
\ No newline at end of file
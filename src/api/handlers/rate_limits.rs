@@ -0,0 +1,29 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    Json,
+};
+use crate::AppState;
+use crate::api::types::{ApiResponse, RateLimitInfo};
+use crate::content::secret::fingerprint;
+
+pub async fn list_buckets(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<RateLimitInfo>>>, StatusCode> {
+    let buckets = state.proxy_engine.rate_limit_snapshot()
+        .into_iter()
+        .map(|bucket| RateLimitInfo {
+            rule: bucket.rule,
+            // Header keys are usually API keys.
+            key: match bucket.key.strip_prefix("header:") {
+                Some(value) => format!("header:{}", fingerprint(value)),
+                None => bucket.key,
+            },
+            tokens: bucket.tokens,
+            capacity: bucket.capacity,
+            full_in_seconds: bucket.full_in.as_secs_f64(),
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(buckets)))
+}
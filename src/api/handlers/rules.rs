@@ -4,24 +4,28 @@ use axum::{
     Json,
 };
 use crate::{AppState, config::ForwardingRule};
-use crate::api::types::{ApiResponse, RuleTestRequest, RuleTestResult};
+use crate::api::types::{ApiResponse, RuleTestRequest, RuleTestResult, WebSocketEvent};
 
 pub async fn list_rules(
     State(state): State<AppState>,
 ) -> Result<Json<ApiResponse<Vec<ForwardingRule>>>, StatusCode> {
     let rules = state.proxy_engine.get_rules().await;
-    Ok(Json(ApiResponse::success(rules)))
+    Ok(Json(ApiResponse::success(rules.iter().map(ForwardingRule::redacted).collect())))
 }
 
 pub async fn create_rule(
     State(state): State<AppState>,
-    Json(rule): Json<ForwardingRule>,
+    Json(mut rule): Json<ForwardingRule>,
 ) -> Result<Json<ApiResponse<String>>, StatusCode> {
     let mut config = state.config.write().await;
     
     if config.forwarding_rules.iter().any(|r| r.name == rule.name) {
         return Ok(Json(ApiResponse::error(format!("Rule '{}' already exists", rule.name))));
     }
+
+    if let Err(e) = rule.restore_secrets(None) {
+        return Ok(Json(ApiResponse::error(format!("Invalid rule: {}", e))));
+    }
     
     config.forwarding_rules.push(rule.clone());
     
@@ -29,6 +33,7 @@ pub async fn create_rule(
         config.forwarding_rules.pop();
         return Ok(Json(ApiResponse::error(format!("Failed to update proxy rules: {}", e))));
     }
+    let _ = state.events.send(WebSocketEvent::rule_updated(&rule));
     
     Ok(Json(ApiResponse::success(format!("Rule '{}' created successfully", rule.name))))
 }
@@ -36,12 +41,17 @@ pub async fn create_rule(
 pub async fn update_rule(
     State(state): State<AppState>,
     Path(name): Path<String>,
-    Json(updated_rule): Json<ForwardingRule>,
+    Json(mut updated_rule): Json<ForwardingRule>,
 ) -> Result<Json<ApiResponse<String>>, StatusCode> {
     let mut config = state.config.write().await;
     
     if let Some(rule) = config.forwarding_rules.iter_mut().find(|r| r.name == name) {
+        if let Err(e) = updated_rule.restore_secrets(Some(rule)) {
+            return Ok(Json(ApiResponse::error(format!("Invalid rule: {}", e))));
+        }
+
         let old_rule = rule.clone();
+        let event = WebSocketEvent::rule_updated(&updated_rule);
         *rule = updated_rule;
         
         let rules_clone = config.forwarding_rules.clone();
@@ -54,6 +64,7 @@ pub async fn update_rule(
             }
             return Ok(Json(ApiResponse::error(format!("Failed to update proxy rules: {}", e))));
         }
+        let _ = state.events.send(event);
         
         Ok(Json(ApiResponse::success(format!("Rule '{}' updated successfully", name))))
     } else {
@@ -74,6 +85,7 @@ pub async fn delete_rule(
             config.forwarding_rules.insert(pos, removed_rule);
             return Ok(Json(ApiResponse::error(format!("Failed to update proxy rules: {}", e))));
         }
+        let _ = state.events.send(WebSocketEvent::config_changed(&config));
         
         Ok(Json(ApiResponse::success(format!("Rule '{}' deleted successfully", name))))
     } else {
@@ -5,6 +5,7 @@ use tower_http::{
     trace::TraceLayer,
 };
 
+#[allow(dead_code)]
 pub fn create_middleware_stack() -> impl tower::Layer<axum::routing::Route> {
     ServiceBuilder::new()
         .layer(CorsLayer::permissive())
@@ -21,8 +21,14 @@ pub fn create_api_router() -> Router<AppState> {
         .route("/api/rules/:name", delete(handlers::rules::delete_rule))
         .route("/api/rules/:name/test", post(handlers::rules::test_rule))
         .route("/api/content/sources", get(handlers::content::list_sources))
+        .route("/api/content/sources/:id/refresh", post(handlers::content::refresh_source))
+        .route("/api/content/sources/:id/cache", delete(handlers::content::invalidate_source))
+        .route("/api/content/sources/:id/preview", get(handlers::content::preview_source))
         .route("/api/content/cache/clear", post(handlers::content::clear_cache))
         .route("/api/content/cache/stats", get(handlers::content::cache_stats))
+        .route("/api/content/usage", get(handlers::content::usage_stats))
+        .route("/api/rate-limits", get(handlers::rate_limits::list_buckets))
+        .route("/api/cache/purge", post(handlers::cache::purge))
         .route("/api/metrics", get(handlers::metrics::get_metrics))
         .route("/api/health", get(handlers::metrics::health_check))
         .route("/api/status", get(handlers::metrics::get_status))
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use crate::config::{Config, ForwardingRule};
+use crate::proxy::circuit_breaker::{CircuitState, CircuitTransition};
+use crate::util::iso8601;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiResponse<T> {
@@ -27,7 +30,7 @@ impl<T> ApiResponse<T> {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemMetrics {
     pub request_count: u64,
     pub error_count: u64,
@@ -38,7 +41,7 @@ pub struct SystemMetrics {
     pub rule_metrics: HashMap<String, RuleMetrics>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RuleMetrics {
     pub request_count: u64,
     pub error_count: u64,
@@ -46,12 +49,28 @@ pub struct RuleMetrics {
     pub backend_health: HashMap<String, BackendHealth>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackendHealth {
     pub is_healthy: bool,
     pub last_check: String,
     pub response_time: f64,
     pub error_count: u32,
+    /// `closed`, `open` or `half_open`.
+    pub circuit_state: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackendStatus {
+    pub rule: String,
+    pub target: String,
+    /// `closed`, `open` or `half_open`.
+    pub circuit_state: String,
+    pub consecutive_failures: u32,
+    pub failure_rate: f64,
+    /// Seconds until an open circuit lets a probe through.
+    pub retry_in_seconds: Option<f64>,
+    pub in_flight: usize,
+    pub response_time_ms: Option<f64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -60,7 +79,11 @@ pub struct CacheStats {
     pub hit_count: u64,
     pub miss_count: u64,
     pub hit_ratio: f64,
+    pub eviction_count: u64,
+    pub expired_count: u64,
     pub memory_usage_bytes: usize,
+    pub max_entries: usize,
+    pub max_bytes: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -70,6 +93,50 @@ pub struct ContentSourceInfo {
     pub last_updated: Option<String>,
     pub cache_ttl: u64,
     pub content_count: usize,
+    pub size_bytes: usize,
+    pub hit_count: u64,
+    pub fetch_duration_ms: Option<u64>,
+    pub stale: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContentPreview {
+    pub identifier: String,
+    pub source_type: String,
+    pub value_count: usize,
+    /// Values in source order; fingerprints for `secret` sources.
+    pub values: Vec<String>,
+    pub weights: Vec<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValueUsageInfo {
+    pub key: String,
+    pub value: String,
+    pub window_count: u32,
+    pub day_count: u64,
+    pub total_count: u64,
+    pub exhausted: bool,
+    pub exhausted_for_seconds: Option<u64>,
+    pub last_status: Option<u16>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateLimitInfo {
+    pub rule: String,
+    /// `ip:<addr>`, `header:<fingerprint>` or `global`.
+    pub key: String,
+    pub tokens: f64,
+    pub capacity: u32,
+    pub full_in_seconds: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachePurgeRequest {
+    /// Only purge this rule's cache; every rule's if omitted.
+    pub rule: Option<String>,
+    /// Only purge URLs (path and query) starting with this prefix.
+    pub url_prefix: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -105,13 +172,39 @@ pub struct RuleTestResult {
     pub applied_replacements: HashMap<String, String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum WebSocketEvent {
     MetricsUpdate { data: SystemMetrics },
     ConfigChanged { config: Config },
-    RuleUpdated { rule: ForwardingRule },
+    RuleUpdated { rule: Box<ForwardingRule> },
     Error { message: String },
     CacheOperation { operation: String, source: String },
-    BackendHealthChanged { backend: String, health: BackendHealth },
-}
\ No newline at end of file
+    BackendHealthChanged { rule: String, backend: String, health: BackendHealth },
+}
+
+impl WebSocketEvent {
+    /// Events go to every connected client, so secret values are fingerprinted.
+    pub fn config_changed(config: &Config) -> Self {
+        Self::ConfigChanged { config: config.redacted() }
+    }
+
+    pub fn rule_updated(rule: &ForwardingRule) -> Self {
+        Self::RuleUpdated { rule: Box::new(rule.redacted()) }
+    }
+
+    pub fn backend_health_changed(transition: &CircuitTransition, response_time_ms: Option<f64>) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        Self::BackendHealthChanged {
+            rule: transition.rule.clone(),
+            backend: transition.target.clone(),
+            health: BackendHealth {
+                is_healthy: transition.to == CircuitState::Closed,
+                last_check: iso8601(now),
+                response_time: response_time_ms.unwrap_or(0.0),
+                error_count: transition.consecutive_failures,
+                circuit_state: transition.to.as_str().to_string(),
+            },
+        }
+    }
+}
@@ -6,6 +6,7 @@ use axum::{
     response::Response,
 };
 use futures::{sink::SinkExt, stream::StreamExt};
+use tokio::sync::broadcast;
 use tokio::time::{interval, Duration};
 use tracing::{error, info};
 use crate::AppState;
@@ -18,8 +19,10 @@ pub async fn websocket_handler(
     ws.on_upgrade(|socket| websocket_connection(socket, state))
 }
 
-async fn websocket_connection(socket: WebSocket, _state: AppState) {
+async fn websocket_connection(socket: WebSocket, state: AppState) {
     let (mut sender, mut receiver) = socket.split();
+    let mut circuit_transitions = state.proxy_engine.subscribe_circuit_transitions();
+    let mut events = state.events.subscribe();
     
     info!("WebSocket connection established");
     
@@ -45,6 +48,34 @@ async fn websocket_connection(socket: WebSocket, _state: AppState) {
                     _ => {}
                 }
             }
+            transition = circuit_transitions.recv() => {
+                let transition = match transition {
+                    Ok(transition) => transition,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let latency = state.proxy_engine.target_latency_ms(&transition.rule, &transition.target);
+                let event = WebSocketEvent::backend_health_changed(&transition, latency);
+                if let Ok(json) = serde_json::to_string(&event) {
+                    if sender.send(Message::Text(json)).await.is_err() {
+                        error!("Failed to send WebSocket message");
+                        break;
+                    }
+                }
+            }
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if let Ok(json) = serde_json::to_string(&event) {
+                    if sender.send(Message::Text(json)).await.is_err() {
+                        error!("Failed to send WebSocket message");
+                        break;
+                    }
+                }
+            }
             _ = interval.tick() => {
                 // Send periodic metrics updates
                 let event = WebSocketEvent::MetricsUpdate {
@@ -5,6 +5,8 @@ use std::collections::HashMap;
 pub struct Config {
     pub server: ServerConfig,
     pub logging: LoggingConfig,
+    #[serde(default)]
+    pub content_cache: ContentCacheConfig,
     pub forwarding_rules: Vec<ForwardingRule>,
 }
 
@@ -21,6 +23,16 @@ pub struct LoggingConfig {
     pub file: Option<String>,
 }
 
+/// Limits for the replacement content cache. Applied at startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentCacheConfig {
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+    /// Total size of cached values, in bytes.
+    #[serde(default = "default_cache_max_bytes")]
+    pub max_bytes: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ForwardingRule {
     pub name: String,
@@ -30,25 +42,391 @@ pub struct ForwardingRule {
     #[serde(default)]
     pub header_replacements: HashMap<String, ContentSource>,
     #[serde(default)]
-    pub body_replacements: HashMap<String, ContentSource>,
+    pub body_replacements: HashMap<String, BodyReplacement>,
+    /// Body replacements applied to the upstream response.
+    #[serde(default)]
+    pub response_body_replacements: HashMap<String, BodyReplacement>,
+    /// Media types whose bodies receive body replacements; empty means text-like types.
+    #[serde(default)]
+    pub replace_content_types: Vec<String>,
+    /// Largest body, before or after removing its `Content-Encoding`, that
+    /// body replacements are applied to; larger bodies are rejected.
+    #[serde(default = "default_max_replacement_body_size")]
+    pub max_replacement_body_size: usize,
+    /// Header operations applied to the forwarded request, after `header_replacements`.
+    #[serde(default)]
+    pub request_headers: Vec<HeaderOperation>,
+    /// Header operations applied to the upstream response.
+    #[serde(default)]
+    pub response_headers: Vec<HeaderOperation>,
+    /// Caches upstream responses to GET requests when set.
+    #[serde(default)]
+    pub cache: Option<ResponseCacheConfig>,
+    /// Token-bucket limit on requests matching the rule.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// In-flight request limits for the rule and each of its targets.
+    #[serde(default)]
+    pub concurrency: Option<ConcurrencyConfig>,
+    /// Key and table for the `consistent_hash` strategy.
+    #[serde(default)]
+    pub consistent_hash: Option<ConsistentHashConfig>,
+    /// Pins clients to a target with an affinity cookie.
+    #[serde(default)]
+    pub sticky: Option<StickyConfig>,
+    /// Stops sending requests to failing targets for a while.
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Timeouts and connection settings for requests to the targets.
+    #[serde(default)]
+    pub upstream: Option<UpstreamConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseCacheConfig {
+    #[serde(default)]
+    pub backend: ResponseCacheBackend,
+    /// Directory holding cached responses (`disk` backend).
+    #[serde(default)]
+    pub directory: Option<String>,
+    /// Seconds to cache responses without `Cache-Control` or `Expires`; with
+    /// 0 they are only kept if they can be revalidated.
+    #[serde(default)]
+    pub default_ttl: u64,
+    /// Maximum number of cached URLs; the least recently used are evicted.
+    #[serde(default = "default_response_cache_max_entries")]
+    pub max_entries: usize,
+    /// Maximum total size of the cached responses, in bytes.
+    #[serde(default = "default_response_cache_max_bytes")]
+    pub max_bytes: usize,
+    /// Larger responses are not cached, in bytes.
+    #[serde(default = "default_response_cache_max_body_size")]
+    pub max_body_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseCacheBackend {
+    #[default]
+    Memory,
+    Disk,
+}
+
+/// Each client gets a bucket of `burst` tokens, refilled at `requests` per
+/// `period` seconds; a request takes one token or is rejected with a 429.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub requests: u32,
+    #[serde(default = "default_rate_limit_period")]
+    pub period: u64,
+    /// Bucket capacity; defaults to `requests`.
+    #[serde(default)]
+    pub burst: Option<u32>,
+    #[serde(default)]
+    pub key: RateLimitKey,
+}
+
+impl RateLimitConfig {
+    pub fn capacity(&self) -> u32 {
+        self.burst.unwrap_or(self.requests)
+    }
+}
+
+/// Requests over an in-flight limit wait in a queue of up to `max_queue`
+/// requests for `queue_timeout_ms`, and are rejected with a 503 otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcurrencyConfig {
+    #[serde(default)]
+    pub max_in_flight: Option<usize>,
+    #[serde(default)]
+    pub max_in_flight_per_target: Option<usize>,
+    #[serde(default = "default_max_queue")]
+    pub max_queue: usize,
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsistentHashConfig {
+    #[serde(default)]
+    pub key: HashKey,
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
+    /// Points per target on the `ring`.
+    #[serde(default = "default_virtual_nodes")]
+    pub virtual_nodes: usize,
+}
+
+impl Default for ConsistentHashConfig {
+    fn default() -> Self {
+        Self {
+            key: HashKey::default(),
+            algorithm: HashAlgorithm::default(),
+            virtual_nodes: default_virtual_nodes(),
+        }
+    }
+}
+
+/// Request data hashed by the `consistent_hash` strategy. Requests without
+/// the header, cookie or capture are hashed by client IP.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HashKey {
+    #[default]
+    Ip,
+    Header { name: String },
+    Cookie { name: String },
+    /// A path capture: a `{name}` segment, or `"1"`, `"2"`, ... for wildcards.
+    Capture { name: String },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    /// A hash ring with `virtual_nodes` points per target.
+    #[default]
+    Ring,
+    /// A Maglev lookup table: more even spread, slightly more remapping.
+    Maglev,
+}
+
+/// Clients without a valid affinity cookie are balanced as usual and get a
+/// cookie naming the chosen target; later requests go to that target.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StickyConfig {
+    #[serde(default = "default_sticky_cookie")]
+    pub cookie: String,
+    /// Cookie lifetime in seconds; a session cookie if unset.
+    #[serde(default)]
+    pub max_age: Option<u64>,
 }
 
+/// A target's circuit opens after `consecutive_failures` failed requests in
+/// a row, or when `failure_rate` of the last `window` requests failed. After
+/// `open_duration` seconds, `half_open_probes` requests are let through; the
+/// circuit closes if they all succeed and opens again otherwise.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// 0 disables the consecutive failure threshold.
+    #[serde(default = "default_consecutive_failures")]
+    pub consecutive_failures: u32,
+    /// Share of failed requests, between 0 and 1.
+    #[serde(default)]
+    pub failure_rate: Option<f64>,
+    #[serde(default = "default_breaker_window")]
+    pub window: usize,
+    /// Requests needed in the window before `failure_rate` applies.
+    #[serde(default = "default_breaker_min_requests")]
+    pub min_requests: usize,
+    #[serde(default = "default_open_duration")]
+    pub open_duration: u64,
+    #[serde(default = "default_half_open_probes")]
+    pub half_open_probes: u32,
+    /// Upstream statuses counted as failures, besides connection errors.
+    #[serde(default = "default_failure_statuses")]
+    pub failure_statuses: Vec<u16>,
+}
+
+/// How a rule connects to its targets. Requests that time out are answered
+/// with `504 Gateway Timeout`; unset connect and request timeouts fall back
+/// to 10 seconds and 5 minutes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpstreamConfig {
+    /// Time allowed to establish a connection.
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    /// Time allowed for the whole upstream request, including the response body.
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+    /// Longest wait for the next chunk of the response body.
+    #[serde(default)]
+    pub idle_timeout_ms: Option<u64>,
+    /// Idle connections kept per target.
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// How long idle pooled connections are kept.
+    #[serde(default)]
+    pub pool_idle_timeout_ms: Option<u64>,
+    /// Speak HTTP/2 without negotiation, e.g. to h2c backends.
+    #[serde(default)]
+    pub http2_prior_knowledge: bool,
+    /// Interval of TCP keepalive probes on upstream connections.
+    #[serde(default)]
+    pub tcp_keepalive_ms: Option<u64>,
+}
+
+/// What identifies a client for rate limiting.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// The client IP address.
+    #[default]
+    Ip,
+    /// A request header value, such as an API key. Requests without the
+    /// header are keyed by client IP.
+    Header { name: String },
+    /// One bucket shared by all clients.
+    Global,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum LoadBalancingStrategy {
+    #[default]
     RoundRobin,
     Random,
     WeightedRoundRobin,
+    /// The target with the fewest requests in flight.
+    LeastConnections,
+    /// The target with the lowest average response time, weighted by its
+    /// requests in flight.
+    LeastResponseTime,
+    /// The less loaded of two random targets.
+    #[serde(alias = "p2c")]
+    PowerOfTwoChoices,
+    /// The target a hash of the request's `consistent_hash` key maps to.
+    ConsistentHash,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContentSource {
     pub source: SourceType,
+    /// File, directory or SQLite database path.
     pub path: Option<String>,
     pub url: Option<String>,
+    /// Values listed directly in the configuration (`inline` sources).
+    #[serde(default)]
+    pub values: Option<Vec<String>>,
+    /// Environment variable name (`env` sources).
+    #[serde(default)]
+    pub variable: Option<String>,
+    /// Executable and arguments (`command` sources).
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Query returning a single column (`sqlite` sources).
+    #[serde(default)]
+    pub query: Option<String>,
+    /// Timeout in seconds for fetching the content.
+    #[serde(default = "default_source_timeout")]
+    pub timeout: u64,
+    /// Extra request headers (`remote` sources).
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Credentials sent with the request (`remote` sources).
+    #[serde(default)]
+    pub auth: Option<RemoteAuth>,
+    /// Largest response body accepted, in bytes (`remote` sources).
+    #[serde(default = "default_max_response_size")]
+    pub max_response_size: u64,
+    #[serde(default)]
     pub split_by: SplitStrategy,
+    /// JSONPath selecting values (`json` split); defaults to a top-level array.
+    #[serde(default)]
+    pub json_path: Option<String>,
+    /// Column name (`csv` split); defaults to the first column.
+    #[serde(default)]
+    pub column: Option<String>,
+    /// Regex whose first capture group, or whole match, is a value (`regex` split).
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Separator string (`delimiter` split).
+    #[serde(default)]
+    pub delimiter: Option<String>,
+    /// How a value is picked from the source for each request.
+    #[serde(default)]
+    pub selection: SelectionStrategy,
+    /// Header whose value is hashed by `sticky_header` selection.
+    #[serde(default)]
+    pub sticky_header: Option<String>,
+    /// Per-value weights for `inline` sources, parallel to `values`.
+    #[serde(default)]
+    pub weights: Option<Vec<u32>>,
+    /// CSV column holding per-value weights (`csv` split).
+    #[serde(default)]
+    pub weight_column: Option<String>,
+    /// Per-value usage limits; exhausted values are skipped during selection.
+    #[serde(default)]
+    pub quota: Option<QuotaConfig>,
     #[serde(default = "default_cache_ttl")]
     pub cache_ttl: u64,
+    /// Seconds before `cache_ttl` expiry at which a request triggers a
+    /// background refresh while the cached content keeps being served; 0 disables.
+    #[serde(default)]
+    pub refresh_ahead: u64,
+    /// Seconds past expiry that the last-known-good content may be served
+    /// when reloading the source fails.
+    #[serde(default = "default_max_stale")]
+    pub max_stale: u64,
+    /// What to do when the content cannot be loaded at all.
+    #[serde(default)]
+    pub on_error: ContentErrorPolicy,
+    /// Value used by the `fallback` error policy.
+    #[serde(default)]
+    pub fallback_value: Option<String>,
+    /// Template rendered per request, with `{{ value }}` bound to the selected content.
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Decrypts the file with AES-256-GCM (`file` sources).
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+    /// Values are credentials: wiped from memory when evicted and only shown
+    /// as fingerprints in logs and API responses.
+    #[serde(default)]
+    pub secret: bool,
+}
+
+/// A header operation. Operations that write a value take either a static
+/// `value` or a `content` source; `rename` moves all values to `to`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderOperation {
+    pub op: HeaderOperationKind,
+    pub name: String,
+    #[serde(default)]
+    pub value: Option<String>,
+    #[serde(default)]
+    pub content: Option<ContentSource>,
+    #[serde(default)]
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderOperationKind {
+    Remove,
+    Set,
+    Append,
+    SetIfAbsent,
+    Rename,
+}
+
+/// A body replacement: the key of the `body_replacements` map is interpreted
+/// according to `mode` (literal text, regex, JSONPath, form field or XPath).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BodyReplacement {
+    #[serde(flatten)]
+    pub content: ContentSource,
+    #[serde(default)]
+    pub mode: ReplacementMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplacementMode {
+    /// Replace every occurrence of the pattern text.
+    #[default]
+    Literal,
+    /// Pattern is a regex; `$1`/`${name}` in the source's `template` expand
+    /// to captures. Values themselves are always inserted verbatim.
+    Regex,
+    /// Pattern is a JSONPath (`$.auth.api_key`) or JSON Pointer (`/auth/api_key`).
+    JsonPath,
+    /// Pattern is the name of an `application/x-www-form-urlencoded` field.
+    FormField,
+    /// Pattern is an XPath subset: `/a/b`, `//b`, optionally ending in `/@attr`.
+    #[serde(rename = "xpath")]
+    XPath,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,20 +434,194 @@ pub struct ContentSource {
 pub enum SourceType {
     File,
     Remote,
+    Inline,
+    Env,
+    Directory,
+    Command,
+    Sqlite,
+}
+
+/// Where the base64-encoded 32-byte key for an encrypted file comes from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    #[serde(default)]
+    pub key_env: Option<String>,
+    #[serde(default)]
+    pub key_file: Option<String>,
 }
 
+/// Credentials for a remote source. Secrets are read from environment
+/// variables when the content is fetched, so they never appear in the config.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RemoteAuth {
+    Bearer {
+        token_env: String,
+    },
+    Basic {
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        username_env: Option<String>,
+        #[serde(default)]
+        password_env: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaConfig {
+    /// Maximum uses of each value per `rate_window` seconds.
+    #[serde(default)]
+    pub rate_limit: Option<u32>,
+    #[serde(default = "default_rate_window")]
+    pub rate_window: u64,
+    /// Maximum uses of each value per UTC day.
+    #[serde(default)]
+    pub daily_quota: Option<u64>,
+    /// Seconds a value is skipped after an `exhaust_on` response without `Retry-After`.
+    #[serde(default = "default_quota_cooldown")]
+    pub cooldown: u64,
+    /// Upstream status codes that mark the value used for the request as exhausted.
+    #[serde(default = "default_exhaust_on")]
+    pub exhaust_on: Vec<u16>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentErrorPolicy {
+    /// Reject the request with a 500.
+    #[default]
+    Fail,
+    /// Forward the request without this replacement.
+    Skip,
+    /// Use `fallback_value` instead.
+    Fallback,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionStrategy {
+    #[default]
+    RoundRobin,
+    Random,
+    /// Smooth weighted round robin over `weights` or `weight_column`.
+    Weighted,
+    /// Same client IP always gets the same value.
+    StickyClientIp,
+    /// Same `sticky_header` value always gets the same value.
+    StickyHeader,
+    LeastRecentlyUsed,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum SplitStrategy {
+    #[default]
     Line,
     Comma,
     Space,
+    Delimiter,
+    Json,
+    Csv,
+    Regex,
+}
+
+fn default_cache_max_entries() -> usize {
+    1000
+}
+
+fn default_cache_max_bytes() -> usize {
+    64 * 1024 * 1024 // 64 MiB
+}
+
+fn default_response_cache_max_entries() -> usize {
+    10_000
+}
+
+fn default_max_replacement_body_size() -> usize {
+    10 * 1024 * 1024 // 10 MiB
+}
+
+fn default_response_cache_max_bytes() -> usize {
+    256 * 1024 * 1024 // 256 MiB
+}
+
+fn default_response_cache_max_body_size() -> usize {
+    1024 * 1024 // 1 MiB
+}
+
+fn default_rate_limit_period() -> u64 {
+    1
+}
+
+fn default_max_queue() -> usize {
+    100
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_virtual_nodes() -> usize {
+    160
+}
+
+fn default_sticky_cookie() -> String {
+    "ultiproxy_affinity".to_string()
+}
+
+fn default_consecutive_failures() -> u32 {
+    5
+}
+
+fn default_breaker_window() -> usize {
+    20
+}
+
+fn default_breaker_min_requests() -> usize {
+    10
+}
+
+fn default_open_duration() -> u64 {
+    30
+}
+
+fn default_half_open_probes() -> u32 {
+    1
+}
+
+fn default_failure_statuses() -> Vec<u16> {
+    vec![502, 503, 504]
 }
 
 fn default_cache_ttl() -> u64 {
     300 // 5 minutes
 }
 
+fn default_source_timeout() -> u64 {
+    10
+}
+
+fn default_max_stale() -> u64 {
+    3600 // 1 hour
+}
+
+fn default_max_response_size() -> u64 {
+    10 * 1024 * 1024 // 10 MiB
+}
+
+fn default_rate_window() -> u64 {
+    60
+}
+
+fn default_quota_cooldown() -> u64 {
+    60
+}
+
+fn default_exhaust_on() -> Vec<u16> {
+    vec![401, 429]
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
@@ -80,18 +632,21 @@ impl Default for ServerConfig {
     }
 }
 
-impl Default for LoggingConfig {
+impl Default for ContentCacheConfig {
     fn default() -> Self {
         Self {
-            level: "info".to_string(),
-            file: None,
+            max_entries: default_cache_max_entries(),
+            max_bytes: default_cache_max_bytes(),
         }
     }
 }
 
-impl Default for LoadBalancingStrategy {
+impl Default for LoggingConfig {
     fn default() -> Self {
-        Self::RoundRobin
+        Self {
+            level: "info".to_string(),
+            file: None,
+        }
     }
 }
 
@@ -107,22 +662,194 @@ impl Config {
             return Err(anyhow::anyhow!("At least one forwarding rule is required"));
         }
 
+        if self.content_cache.max_entries == 0 || self.content_cache.max_bytes == 0 {
+            return Err(anyhow::anyhow!("content_cache limits must be greater than 0"));
+        }
+
         for rule in &self.forwarding_rules {
-            if rule.target_urls.is_empty() {
-                return Err(anyhow::anyhow!("Rule '{}' must have at least one target URL", rule.name));
+            rule.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// A copy safe to return from the API, with secret values fingerprinted.
+    pub fn redacted(&self) -> Self {
+        Self {
+            forwarding_rules: self.forwarding_rules.iter().map(ForwardingRule::redacted).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Puts back the secret values that `redacted` fingerprinted, so a
+    /// configuration read from the API can be sent back unchanged.
+    pub fn restore_secrets(&mut self, current: &Config) -> anyhow::Result<()> {
+        for rule in &mut self.forwarding_rules {
+            let previous = current.forwarding_rules.iter().find(|r| r.name == rule.name);
+            rule.restore_secrets(previous)?;
+        }
+        Ok(())
+    }
+}
+
+impl ForwardingRule {
+    /// Checks one rule; also run by the router, since rules can be changed
+    /// through the API without a full `Config::validate`.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.target_urls.is_empty() {
+            return Err(anyhow::anyhow!("Rule '{}' must have at least one target URL", self.name));
+        }
+
+        for (key, source) in &self.header_replacements {
+            source.validate(&format!("header replacement '{}'", key))?;
+        }
+
+        for (pattern, replacement) in &self.body_replacements {
+            replacement.validate(pattern, &format!("body replacement '{}'", pattern))?;
+        }
+
+        for (pattern, replacement) in &self.response_body_replacements {
+            replacement.validate(pattern, &format!("response body replacement '{}'", pattern))?;
+        }
+
+        for operation in &self.request_headers {
+            operation.validate(&format!("rule '{}' request header '{}'", self.name, operation.name))?;
+        }
+
+        for operation in &self.response_headers {
+            operation.validate(&format!("rule '{}' response header '{}'", self.name, operation.name))?;
+        }
+
+        if let Some(cache) = &self.cache {
+            if cache.backend == ResponseCacheBackend::Disk && cache.directory.is_none() {
+                return Err(anyhow::anyhow!("Rule '{}': disk response cache requires 'directory' field", self.name));
+            }
+            if cache.max_entries == 0 {
+                return Err(anyhow::anyhow!("Rule '{}': response cache max_entries must be greater than 0", self.name));
+            }
+            if cache.max_bytes == 0 {
+                return Err(anyhow::anyhow!("Rule '{}': response cache max_bytes must be greater than 0", self.name));
+            }
+        }
+
+        if let Some(rate_limit) = &self.rate_limit {
+            if rate_limit.requests == 0 || rate_limit.period == 0 || rate_limit.capacity() == 0 {
+                return Err(anyhow::anyhow!("Rule '{}': rate limit requests, period and burst must be greater than 0", self.name));
+            }
+            if let RateLimitKey::Header { name } = &rate_limit.key {
+                if axum::http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                    return Err(anyhow::anyhow!("Rule '{}': invalid rate limit header name '{}'", self.name, name));
+                }
+            }
+        }
+
+        if let Some(concurrency) = &self.concurrency {
+            if concurrency.max_in_flight == Some(0) || concurrency.max_in_flight_per_target == Some(0) {
+                return Err(anyhow::anyhow!("Rule '{}': in-flight limits must be greater than 0", self.name));
+            }
+        }
+
+        if let Some(consistent_hash) = &self.consistent_hash {
+            if consistent_hash.virtual_nodes == 0 {
+                return Err(anyhow::anyhow!("Rule '{}': consistent_hash virtual_nodes must be greater than 0", self.name));
+            }
+            if let HashKey::Header { name } = &consistent_hash.key {
+                if axum::http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                    return Err(anyhow::anyhow!("Rule '{}': invalid consistent_hash header name '{}'", self.name, name));
+                }
+            }
+        }
+
+        if let Some(breaker) = &self.circuit_breaker {
+            if breaker.failure_rate.is_some_and(|rate| !(rate > 0.0 && rate <= 1.0)) {
+                return Err(anyhow::anyhow!("Rule '{}': circuit breaker failure_rate must be between 0 and 1", self.name));
+            }
+            if breaker.consecutive_failures == 0 && breaker.failure_rate.is_none() {
+                return Err(anyhow::anyhow!("Rule '{}': circuit breaker needs consecutive_failures or failure_rate", self.name));
             }
+            if breaker.window == 0 || breaker.half_open_probes == 0 {
+                return Err(anyhow::anyhow!("Rule '{}': circuit breaker window and half_open_probes must be greater than 0", self.name));
+            }
+        }
 
-            for (key, source) in &rule.header_replacements {
-                source.validate(&format!("header replacement '{}'", key))?;
+        if let Some(upstream) = &self.upstream {
+            let timeouts = [upstream.connect_timeout_ms, upstream.request_timeout_ms, upstream.idle_timeout_ms];
+            if timeouts.contains(&Some(0)) {
+                return Err(anyhow::anyhow!("Rule '{}': upstream timeouts must be greater than 0", self.name));
             }
+        }
 
-            for (key, source) in &rule.body_replacements {
-                source.validate(&format!("body replacement '{}'", key))?;
+        if let Some(sticky) = &self.sticky {
+            let valid = !sticky.cookie.is_empty()
+                && sticky.cookie.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+            if !valid {
+                return Err(anyhow::anyhow!("Rule '{}': invalid sticky cookie name '{}'", self.name, sticky.cookie));
             }
         }
 
         Ok(())
     }
+
+    /// A copy safe to return from the API, with secret values fingerprinted.
+    pub fn redacted(&self) -> Self {
+        let redact_operations = |operations: &[HeaderOperation]| -> Vec<HeaderOperation> {
+            operations.iter()
+                .map(|operation| HeaderOperation {
+                    content: operation.content.as_ref().map(ContentSource::redacted),
+                    ..operation.clone()
+                })
+                .collect()
+        };
+        let redact_body_replacements = |replacements: &HashMap<String, BodyReplacement>| -> HashMap<String, BodyReplacement> {
+            replacements.iter()
+                .map(|(pattern, replacement)| (pattern.clone(), BodyReplacement {
+                    content: replacement.content.redacted(),
+                    mode: replacement.mode,
+                }))
+                .collect()
+        };
+
+        Self {
+            header_replacements: self.header_replacements.iter()
+                .map(|(name, source)| (name.clone(), source.redacted()))
+                .collect(),
+            body_replacements: redact_body_replacements(&self.body_replacements),
+            response_body_replacements: redact_body_replacements(&self.response_body_replacements),
+            request_headers: redact_operations(&self.request_headers),
+            response_headers: redact_operations(&self.response_headers),
+            ..self.clone()
+        }
+    }
+
+    /// Replaces fingerprints in secret sources with the values of `current`
+    /// (the rule being replaced) they stand for. Fingerprints that match no
+    /// current value are rejected rather than used as the secret.
+    pub fn restore_secrets(&mut self, current: Option<&ForwardingRule>) -> anyhow::Result<()> {
+        let known: Vec<&str> = current.into_iter()
+            .flat_map(ForwardingRule::content_sources)
+            .filter(|source| source.secret)
+            .flat_map(ContentSource::secret_values)
+            .collect();
+
+        let name = self.name.clone();
+        for source in self.content_sources_mut().filter(|source| source.secret) {
+            source.restore_secrets(&known)
+                .map_err(|e| anyhow::anyhow!("Rule '{}': {}", name, e))?;
+        }
+        Ok(())
+    }
+
+    fn content_sources(&self) -> impl Iterator<Item = &ContentSource> {
+        self.header_replacements.values()
+            .chain(self.body_replacements.values().chain(self.response_body_replacements.values()).map(|replacement| &replacement.content))
+            .chain(self.request_headers.iter().chain(&self.response_headers).filter_map(|operation| operation.content.as_ref()))
+    }
+
+    fn content_sources_mut(&mut self) -> impl Iterator<Item = &mut ContentSource> {
+        self.header_replacements.values_mut()
+            .chain(self.body_replacements.values_mut().chain(self.response_body_replacements.values_mut()).map(|replacement| &mut replacement.content))
+            .chain(self.request_headers.iter_mut().chain(&mut self.response_headers).filter_map(|operation| operation.content.as_mut()))
+    }
 }
 
 impl ContentSource {
@@ -138,7 +865,183 @@ impl ContentSource {
                     return Err(anyhow::anyhow!("{}: remote source requires 'url' field", context));
                 }
             }
+            SourceType::Inline => {
+                if self.values.is_none() {
+                    return Err(anyhow::anyhow!("{}: inline source requires 'values' field", context));
+                }
+            }
+            SourceType::Env => {
+                if self.variable.is_none() {
+                    return Err(anyhow::anyhow!("{}: env source requires 'variable' field", context));
+                }
+            }
+            SourceType::Directory => {
+                if self.path.is_none() {
+                    return Err(anyhow::anyhow!("{}: directory source requires 'path' field", context));
+                }
+            }
+            SourceType::Command => {
+                if self.command.is_none() {
+                    return Err(anyhow::anyhow!("{}: command source requires 'command' field", context));
+                }
+            }
+            SourceType::Sqlite => {
+                if self.path.is_none() || self.query.is_none() {
+                    return Err(anyhow::anyhow!("{}: sqlite source requires 'path' and 'query' fields", context));
+                }
+            }
+        }
+
+        match self.split_by {
+            SplitStrategy::Delimiter if self.delimiter.as_deref().is_none_or(str::is_empty) => {
+                return Err(anyhow::anyhow!("{}: delimiter split requires 'delimiter' field", context));
+            }
+            SplitStrategy::Regex => {
+                let pattern = self.pattern.as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("{}: regex split requires 'pattern' field", context))?;
+                regex::Regex::new(pattern)
+                    .map_err(|e| anyhow::anyhow!("{}: invalid split pattern: {}", context, e))?;
+            }
+            SplitStrategy::Json => {
+                if let Some(path) = &self.json_path {
+                    serde_json_path::JsonPath::parse(path)
+                        .map_err(|e| anyhow::anyhow!("{}: invalid JSONPath: {}", context, e))?;
+                }
+            }
+            _ => {}
+        }
+
+        match self.selection {
+            SelectionStrategy::StickyHeader if self.sticky_header.is_none() => {
+                return Err(anyhow::anyhow!("{}: sticky_header selection requires 'sticky_header' field", context));
+            }
+            SelectionStrategy::Weighted if self.weights.is_none() && self.weight_column.is_none() => {
+                return Err(anyhow::anyhow!("{}: weighted selection requires 'weights' or 'weight_column' field", context));
+            }
+            _ => {}
+        }
+
+        for (name, value) in &self.headers {
+            axum::http::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| anyhow::anyhow!("{}: invalid header name '{}'", context, name))?;
+            axum::http::HeaderValue::from_str(value)
+                .map_err(|_| anyhow::anyhow!("{}: invalid value for header '{}'", context, name))?;
+        }
+
+        if let Some(RemoteAuth::Basic { username, username_env, .. }) = &self.auth {
+            if username.is_some() == username_env.is_some() {
+                return Err(anyhow::anyhow!("{}: basic auth requires exactly one of 'username' or 'username_env'", context));
+            }
+        }
+
+        if let Some(encryption) = &self.encryption {
+            if !matches!(self.source, SourceType::File) {
+                return Err(anyhow::anyhow!("{}: 'encryption' is only supported for file sources", context));
+            }
+            if encryption.key_env.is_some() == encryption.key_file.is_some() {
+                return Err(anyhow::anyhow!("{}: encryption requires exactly one of 'key_env' or 'key_file'", context));
+            }
+        }
+
+        if self.on_error == ContentErrorPolicy::Fallback && self.fallback_value.is_none() {
+            return Err(anyhow::anyhow!("{}: fallback error policy requires 'fallback_value' field", context));
+        }
+
+        if self.max_response_size == 0 {
+            return Err(anyhow::anyhow!("{}: max_response_size must be greater than 0", context));
+        }
+
+        if let Some(quota) = &self.quota {
+            if quota.rate_window == 0 {
+                return Err(anyhow::anyhow!("{}: quota rate_window must be greater than 0", context));
+            }
+        }
+
+        if let (Some(values), Some(weights)) = (&self.values, &self.weights) {
+            if values.len() != weights.len() {
+                return Err(anyhow::anyhow!("{}: 'weights' must have one entry per value", context));
+            }
+        }
+
+        if let Some(template) = &self.template {
+            crate::proxy::template::Template::parse(template)
+                .map_err(|e| anyhow::anyhow!("{}: invalid template: {}", context, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn redacted(&self) -> Self {
+        if !self.secret {
+            return self.clone();
+        }
+
+        let fingerprint = |value: &String| crate::content::secret::fingerprint(value);
+        Self {
+            values: self.values.as_ref().map(|values| values.iter().map(fingerprint).collect()),
+            headers: self.headers.iter()
+                .map(|(name, value)| (name.clone(), fingerprint(value)))
+                .collect(),
+            fallback_value: self.fallback_value.as_ref().map(fingerprint),
+            ..self.clone()
+        }
+    }
+
+    /// The values `redacted` fingerprints.
+    fn secret_values(&self) -> impl Iterator<Item = &str> {
+        self.values.iter().flatten()
+            .chain(self.headers.values())
+            .chain(&self.fallback_value)
+            .map(String::as_str)
+    }
+
+    fn restore_secrets(&mut self, known: &[&str]) -> anyhow::Result<()> {
+        use crate::content::secret::{fingerprint, is_fingerprint};
+
+        let values = self.values.iter_mut().flatten()
+            .chain(self.headers.values_mut())
+            .chain(&mut self.fallback_value);
+        for value in values.filter(|value| is_fingerprint(value)) {
+            match known.iter().find(|secret| fingerprint(secret) == *value) {
+                Some(secret) => *value = secret.to_string(),
+                None => return Err(anyhow::anyhow!("'{}' is the fingerprint of an unknown secret value", value)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BodyReplacement {
+    fn validate(&self, pattern: &str, context: &str) -> anyhow::Result<()> {
+        self.content.validate(context)?;
+        crate::proxy::replacement::validate_pattern(pattern, self.mode)
+            .map_err(|e| anyhow::anyhow!("{}: {}", context, e))
+    }
+}
+
+impl HeaderOperation {
+    fn validate(&self, context: &str) -> anyhow::Result<()> {
+        axum::http::HeaderName::from_bytes(self.name.as_bytes())
+            .map_err(|_| anyhow::anyhow!("{}: invalid header name", context))?;
+
+        match self.op {
+            HeaderOperationKind::Remove => {}
+            HeaderOperationKind::Rename => {
+                let to = self.to.as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("{}: rename requires 'to' field", context))?;
+                axum::http::HeaderName::from_bytes(to.as_bytes())
+                    .map_err(|_| anyhow::anyhow!("{}: invalid header name '{}'", context, to))?;
+            }
+            HeaderOperationKind::Set | HeaderOperationKind::Append | HeaderOperationKind::SetIfAbsent => {
+                match (&self.value, &self.content) {
+                    (Some(value), None) => {
+                        axum::http::HeaderValue::from_str(value)
+                            .map_err(|_| anyhow::anyhow!("{}: invalid header value", context))?;
+                    }
+                    (None, Some(content)) => content.validate(context)?,
+                    _ => return Err(anyhow::anyhow!("{}: exactly one of 'value' or 'content' is required", context)),
+                }
+            }
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
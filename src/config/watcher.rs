@@ -7,7 +7,8 @@ use tracing::{error, info};
 
 pub struct ConfigWatcher {
     _watcher: RecommendedWatcher,
-    pub receiver: broadcast::Receiver<()>,
+    /// Keeps the channel open, so change notifications never fail to send.
+    _receiver: broadcast::Receiver<()>,
 }
 
 impl ConfigWatcher {
@@ -48,7 +49,7 @@ impl ConfigWatcher {
         Ok((
             Self {
                 _watcher: watcher,
-                receiver: rx,
+                _receiver: rx,
             },
             tx,
         ))
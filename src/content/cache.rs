@@ -1,65 +1,329 @@
+use super::sources::ContentSet;
+use crate::config::ContentCacheConfig;
 use dashmap::DashMap;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Debug, Clone)]
 struct CacheEntry {
-    content: Vec<String>,
+    content: ContentSet,
     expires_at: Instant,
+    /// Expired content is kept until this instant as a last-known-good fallback.
+    stale_until: Instant,
+    inserted_at: SystemTime,
+    fetch_duration: Duration,
+    size_bytes: usize,
+    hits: u64,
+    /// Logical clock value of the last read or write, for LRU eviction.
+    last_access: u64,
 }
 
+/// Content cache bounded by entry count and total size. When either limit
+/// is exceeded, entries past their staleness allowance go first, then the
+/// least recently used ones.
 #[derive(Debug)]
 pub struct ContentCache {
     entries: DashMap<String, CacheEntry>,
+    max_entries: usize,
+    max_bytes: usize,
+    bytes: AtomicUsize,
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    expirations: AtomicU64,
+    evictions: AtomicU64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CacheStatsSnapshot {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped after their staleness allowance ran out.
+    pub expirations: u64,
+    /// Entries dropped to stay within the cache limits.
+    pub evictions: u64,
+    pub size_bytes: usize,
+    pub max_entries: usize,
+    pub max_bytes: usize,
+}
+
+/// What the cache knows about one key.
+#[derive(Debug, Clone)]
+pub struct CacheEntryInfo {
+    pub value_count: usize,
+    pub size_bytes: usize,
+    pub inserted_at: SystemTime,
+    pub fetch_duration: Duration,
+    pub hits: u64,
+    /// Whether the entry is past `cache_ttl` and only kept as last-known-good.
+    pub stale: bool,
 }
 
 impl ContentCache {
     pub fn new() -> Self {
+        Self::with_limits(&ContentCacheConfig::default())
+    }
+
+    pub fn with_limits(config: &ContentCacheConfig) -> Self {
         Self {
             entries: DashMap::new(),
+            max_entries: config.max_entries,
+            max_bytes: config.max_bytes,
+            bytes: AtomicUsize::new(0),
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
         }
     }
 
-    pub fn insert(&mut self, key: String, content: Vec<String>, ttl_seconds: u64) {
+    /// Caches `content`, evicting other entries as needed. Returns false,
+    /// caching nothing, if the content alone is larger than the byte budget.
+    pub fn insert(
+        &mut self,
+        key: String,
+        content: ContentSet,
+        ttl_seconds: u64,
+        max_stale_seconds: u64,
+        fetch_duration: Duration,
+    ) -> bool {
+        let size_bytes = content_size(&content);
+        if size_bytes > self.max_bytes {
+            return false;
+        }
+
         let expires_at = Instant::now() + Duration::from_secs(ttl_seconds);
         let entry = CacheEntry {
+            size_bytes,
             content,
             expires_at,
+            stale_until: expires_at + Duration::from_secs(max_stale_seconds),
+            inserted_at: SystemTime::now(),
+            fetch_duration,
+            hits: 0,
+            last_access: self.tick(),
         };
-        self.entries.insert(key, entry);
+        if let Some(previous) = self.entries.insert(key.clone(), entry) {
+            self.bytes.fetch_sub(previous.size_bytes, Ordering::Relaxed);
+        }
+        self.bytes.fetch_add(size_bytes, Ordering::Relaxed);
+
+        self.enforce_limits(&key);
+        true
     }
 
-    pub fn get(&self, key: &str) -> Option<Vec<String>> {
-        if let Some(entry) = self.entries.get(key) {
-            if Instant::now() < entry.expires_at {
-                return Some(entry.content.clone());
-            } else {
+    /// Returns fresh content together with the instant it expires.
+    pub fn get_with_expiry(&self, key: &str) -> Option<(ContentSet, Instant)> {
+        let now = Instant::now();
+        if let Some(mut entry) = self.entries.get_mut(key) {
+            if now < entry.expires_at {
+                entry.hits += 1;
+                entry.last_access = self.tick();
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some((entry.content.clone(), entry.expires_at));
+            } else if now >= entry.stale_until {
                 drop(entry);
-                self.entries.remove(key);
+                if self.remove_entry(key).is_some() {
+                    self.expirations.fetch_add(1, Ordering::Relaxed);
+                }
             }
         }
+        self.misses.fetch_add(1, Ordering::Relaxed);
         None
     }
 
+    /// Returns expired content that is still within its staleness allowance.
+    pub fn get_stale(&self, key: &str) -> Option<ContentSet> {
+        let mut entry = self.entries.get_mut(key)
+            .filter(|entry| Instant::now() < entry.stale_until)?;
+        entry.last_access = self.tick();
+        Some(entry.content.clone())
+    }
+
+    /// Returns the content for `key` if it is still held, fresh or stale,
+    /// without counting it as an access.
+    pub fn peek(&self, key: &str) -> Option<ContentSet> {
+        self.entries.get(key)
+            .filter(|entry| Instant::now() < entry.stale_until)
+            .map(|entry| entry.content.clone())
+    }
+
+    /// Marks an entry as expired, keeping it as last-known-good content.
+    pub fn expire(&mut self, key: &str) {
+        if let Some(mut entry) = self.entries.get_mut(key) {
+            entry.expires_at = Instant::now();
+        }
+    }
+
     pub fn remove(&mut self, key: &str) {
-        self.entries.remove(key);
+        self.remove_entry(key);
     }
 
     pub fn clear(&mut self) {
         self.entries.clear();
+        self.bytes.store(0, Ordering::Relaxed);
     }
 
-    pub fn cleanup_expired(&mut self) {
+    pub fn cleanup_expired(&mut self) -> usize {
         let now = Instant::now();
-        self.entries.retain(|_, entry| now < entry.expires_at);
+        let mut removed = 0;
+        let mut removed_bytes = 0;
+        self.entries.retain(|_, entry| {
+            let keep = now < entry.stale_until;
+            if !keep {
+                removed += 1;
+                removed_bytes += entry.size_bytes;
+            }
+            keep
+        });
+        self.bytes.fetch_sub(removed_bytes, Ordering::Relaxed);
+        self.expirations.fetch_add(removed as u64, Ordering::Relaxed);
+        removed
     }
 
     pub fn size(&self) -> usize {
         self.entries.len()
     }
+
+    pub fn stats(&self) -> CacheStatsSnapshot {
+        CacheStatsSnapshot {
+            entries: self.size(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            size_bytes: self.bytes.load(Ordering::Relaxed),
+            max_entries: self.max_entries,
+            max_bytes: self.max_bytes,
+        }
+    }
+
+    pub fn entry_info(&self, key: &str) -> Option<CacheEntryInfo> {
+        self.entries.get(key).map(|entry| CacheEntryInfo {
+            value_count: entry.content.len(),
+            size_bytes: entry.size_bytes,
+            inserted_at: entry.inserted_at,
+            fetch_duration: entry.fetch_duration,
+            hits: entry.hits,
+            stale: Instant::now() >= entry.expires_at,
+        })
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn remove_entry(&self, key: &str) -> Option<CacheEntry> {
+        let (_, entry) = self.entries.remove(key)?;
+        self.bytes.fetch_sub(entry.size_bytes, Ordering::Relaxed);
+        Some(entry)
+    }
+
+    /// Evicts entries other than `keep` until both limits are met.
+    fn enforce_limits(&self, keep: &str) {
+        while self.entries.len() > self.max_entries || self.bytes.load(Ordering::Relaxed) > self.max_bytes {
+            let now = Instant::now();
+            let victim = self.entries.iter()
+                .filter(|entry| entry.key() != keep)
+                .min_by_key(|entry| (now < entry.stale_until, entry.last_access))
+                .map(|entry| entry.key().clone());
+
+            let Some(victim) = victim else { break };
+            if let Some(entry) = self.remove_entry(&victim) {
+                if now >= entry.stale_until {
+                    self.expirations.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
 }
 
 impl Default for ContentCache {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+/// Approximate heap size of the cached values and weights.
+fn content_size(content: &ContentSet) -> usize {
+    content.values.iter().map(|value| value.len()).sum::<usize>()
+        + content.weights.len() * std::mem::size_of::<u32>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl ContentCache {
+        fn get(&self, key: &str) -> Option<ContentSet> {
+            self.get_with_expiry(key).map(|(content, _)| content)
+        }
+    }
+
+    #[test]
+    fn test_expired_entry_is_kept_as_stale() {
+        let mut cache = ContentCache::new();
+        let content = ContentSet::new(vec!["a".to_string()]);
+
+        cache.insert("fresh".to_string(), content.clone(), 60, 0, Duration::ZERO);
+        assert_eq!(cache.get("fresh"), Some(content.clone()));
+
+        cache.insert("stale".to_string(), content.clone(), 0, 60, Duration::ZERO);
+        assert_eq!(cache.get("stale"), None);
+        assert_eq!(cache.get_stale("stale"), Some(content.clone()));
+
+        cache.insert("gone".to_string(), content, 0, 0, Duration::ZERO);
+        assert_eq!(cache.get_stale("gone"), None);
+        assert_eq!(cache.cleanup_expired(), 1);
+        assert_eq!(cache.size(), 2);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut cache = ContentCache::with_limits(&ContentCacheConfig {
+            max_entries: 2,
+            max_bytes: 10,
+        });
+        let content = |value: &str| ContentSet::new(vec![value.to_string()]);
+
+        assert!(cache.insert("a".to_string(), content("aaa"), 60, 0, Duration::ZERO));
+        assert!(cache.insert("b".to_string(), content("bbb"), 60, 0, Duration::ZERO));
+        cache.get("a");
+
+        // Over the entry limit: "b" is least recently used.
+        assert!(cache.insert("c".to_string(), content("ccc"), 60, 0, Duration::ZERO));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+
+        // Over the byte budget: "c" is now least recently used.
+        assert!(cache.insert("a".to_string(), content("aaaaaaaa"), 60, 0, Duration::ZERO));
+        assert!(cache.get("c").is_none());
+
+        assert!(!cache.insert("huge".to_string(), content("x".repeat(11).as_str()), 60, 0, Duration::ZERO));
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.size_bytes, stats.evictions), (1, 8, 2));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut cache = ContentCache::new();
+        let content = ContentSet::new(vec!["abc".to_string(), "de".to_string()]);
+        cache.insert("key".to_string(), content, 60, 0, Duration::from_millis(5));
+
+        cache.get("key");
+        cache.get("key");
+        cache.get("missing");
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses, stats.size_bytes), (1, 2, 1, 5));
+
+        let info = cache.entry_info("key").unwrap();
+        assert_eq!((info.value_count, info.hits, info.stale), (2, 2, false));
+        assert_eq!(info.fetch_duration, Duration::from_millis(5));
+    }
+}
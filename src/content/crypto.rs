@@ -0,0 +1,99 @@
+use crate::config::EncryptionConfig;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use zeroize::Zeroizing;
+
+/// Prefix identifying files written by `encrypt`, followed by a 12-byte
+/// nonce and the AES-256-GCM ciphertext with its tag.
+const MAGIC: &[u8] = b"ULTIPROXY-AES256GCM-1\n";
+const NONCE_LEN: usize = 12;
+
+/// Reads the base64-encoded 32-byte key from the configured env var or key file.
+pub fn load_key(config: &EncryptionConfig) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+    let encoded = Zeroizing::new(match (&config.key_env, &config.key_file) {
+        (Some(variable), None) => std::env::var(variable)
+            .map_err(|e| anyhow::anyhow!("Failed to read encryption key from {}: {}", variable, e))?,
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read encryption key file {}: {}", path, e))?,
+        _ => return Err(anyhow::anyhow!("Exactly one of 'key_env' or 'key_file' is required")),
+    });
+
+    let key = Zeroizing::new(
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| anyhow::anyhow!("Encryption key is not valid base64: {}", e))?,
+    );
+    if key.len() != 32 {
+        return Err(anyhow::anyhow!("Encryption key must be 32 bytes, got {}", key.len()));
+    }
+    Ok(key)
+}
+
+/// A new random key, base64-encoded as `load_key` expects it.
+pub fn generate_key() -> Zeroizing<String> {
+    let key = Aes256Gcm::generate_key(&mut OsRng);
+    Zeroizing::new(base64::engine::general_purpose::STANDARD.encode(key.as_slice()))
+}
+
+pub fn encrypt(plaintext: &[u8], key: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext)
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+
+    let mut output = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    output.extend_from_slice(MAGIC);
+    output.extend_from_slice(&nonce);
+    output.extend_from_slice(&ciphertext);
+    Ok(output)
+}
+
+pub fn decrypt(data: &[u8], key: &[u8]) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+    let payload = data.strip_prefix(MAGIC)
+        .ok_or_else(|| anyhow::anyhow!("Not an encrypted content file"))?;
+    if payload.len() < NONCE_LEN {
+        return Err(anyhow::anyhow!("Encrypted content file is truncated"));
+    }
+
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| anyhow::anyhow!("Decryption failed: wrong key or corrupted file"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let key = [7u8; 32];
+        let encrypted = encrypt(b"k1\nk2", &key).unwrap();
+        assert!(!encrypted.windows(2).any(|w| w == b"k1"));
+        assert_eq!(decrypt(&encrypted, &key).unwrap().as_slice(), b"k1\nk2");
+
+        assert!(decrypt(&encrypted, &[8u8; 32]).is_err());
+        assert!(decrypt(b"k1\nk2", &key).is_err());
+    }
+
+    #[test]
+    fn test_generate_key() {
+        let encoded = generate_key();
+        let key = base64::engine::general_purpose::STANDARD.decode(encoded.as_str()).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_ne!(encoded, generate_key());
+    }
+
+    #[test]
+    fn test_load_key() {
+        let variable = format!("ULTIPROXY_TEST_KEY_{}", std::process::id());
+        std::env::set_var(&variable, base64::engine::general_purpose::STANDARD.encode([1u8; 32]));
+        let config = EncryptionConfig { key_env: Some(variable), key_file: None };
+        assert_eq!(load_key(&config).unwrap().as_slice(), &[1u8; 32]);
+
+        let config = EncryptionConfig { key_env: None, key_file: None };
+        assert!(load_key(&config).is_err());
+    }
+}
@@ -1,4 +1,7 @@
 pub mod sources;
 pub mod cache;
+pub mod crypto;
+pub mod secret;
+pub mod watcher;
 
 pub use sources::*;
\ No newline at end of file
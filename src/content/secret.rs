@@ -0,0 +1,48 @@
+use sha2::{Digest, Sha256};
+
+/// Identifies a secret without revealing it: the last 4 characters (only
+/// for values long enough that this gives little away) and a short hash.
+pub fn fingerprint(value: &str) -> String {
+    let hash = &digest(value)[..8];
+    let chars: Vec<char> = value.chars().collect();
+    if chars.len() <= 8 {
+        return format!("**** (sha256:{})", hash);
+    }
+    let last4: String = chars[chars.len() - 4..].iter().collect();
+    format!("****{} (sha256:{})", last4, hash)
+}
+
+/// Whether `value` has the shape of a `fingerprint`, as sent back by
+/// clients that edit a redacted configuration.
+pub fn is_fingerprint(value: &str) -> bool {
+    let Some((last4, hash)) = value.strip_prefix("****").and_then(|rest| rest.split_once(" (sha256:")) else {
+        return false;
+    };
+    let hash = hash.strip_suffix(')').unwrap_or_default();
+    last4.chars().count() <= 4 && hash.len() == 8 && hash.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Hex-encoded SHA-256 of `value`, for keys that must not contain the value.
+pub fn digest(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint("sk-live-1234567890abcd"), format!("****abcd (sha256:{})", &digest("sk-live-1234567890abcd")[..8]));
+        assert!(!fingerprint("short").contains("short"));
+        assert_ne!(fingerprint("secret-one-xyz"), fingerprint("secret-two-xyz"));
+
+        assert!(is_fingerprint(&fingerprint("sk-live-1234567890abcd")));
+        assert!(is_fingerprint(&fingerprint("short")));
+        assert!(!is_fingerprint("****abcd"));
+        assert!(!is_fingerprint("sk-live-1234567890abcd"));
+    }
+}
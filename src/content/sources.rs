@@ -1,110 +1,633 @@
-use crate::config::{ContentSource, SourceType, SplitStrategy};
+use crate::config::{ContentCacheConfig, ContentSource, RemoteAuth, SourceType, SplitStrategy};
+use super::cache::{CacheEntryInfo, CacheStatsSnapshot};
+use super::crypto;
+use super::watcher::FileWatcher;
 use anyhow::Result;
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{error, warn};
+use dashmap::{DashMap, DashSet};
+use reqwest::header::{CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use rusqlite::types::ValueRef;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{OnceCell, RwLock};
+use tracing::{debug, error, warn};
+use zeroize::Zeroizing;
+
+/// One loaded value. Clones share a single copy, which is zeroized once the
+/// last clone is dropped, so secrets are not duplicated across the cache,
+/// selectors and quota counters.
+#[derive(Clone)]
+pub struct ContentValue(Arc<Zeroizing<String>>);
+
+impl ContentValue {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for ContentValue {
+    fn from(value: String) -> Self {
+        Self(Arc::new(Zeroizing::new(value)))
+    }
+}
+
+impl From<&str> for ContentValue {
+    fn from(value: &str) -> Self {
+        Self::from(value.to_string())
+    }
+}
+
+impl std::ops::Deref for ContentValue {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl std::borrow::Borrow<str> for ContentValue {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// Values may be secrets, so only their fingerprint is ever formatted.
+impl std::fmt::Debug for ContentValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&super::secret::fingerprint(self.as_str()))
+    }
+}
+
+impl PartialEq for ContentValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for ContentValue {}
+
+impl PartialEq<&str> for ContentValue {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl std::hash::Hash for ContentValue {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+/// Values loaded from a content source, with optional per-value weights.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContentSet {
+    pub values: Vec<ContentValue>,
+    /// Parallel to `values`; empty when the source defines no weights.
+    pub weights: Vec<u32>,
+    /// Copied from the source; secret values are only shown as fingerprints.
+    pub secret: bool,
+}
+
+impl ContentSet {
+    pub fn new(values: Vec<String>) -> Self {
+        Self {
+            values: values.into_iter().map(ContentValue::from).collect(),
+            weights: Vec::new(),
+            secret: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Weight of the value at `index`, defaulting to 1.
+    pub fn weight(&self, index: usize) -> u32 {
+        self.weights.get(index).copied().unwrap_or(1)
+    }
+}
+
+/// The last successful response for a remote source, kept so an expired
+/// cache entry can be revalidated instead of downloaded again.
+#[derive(Debug, Clone)]
+struct RemoteValidator {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: Zeroizing<String>,
+}
+
+/// Delay before a failed source is loaded again, doubled per consecutive failure.
+const FAILURE_BACKOFF_BASE: Duration = Duration::from_secs(1);
+const FAILURE_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// The most recent load failure of a source, returned until `retry_at`
+/// instead of loading (and waiting on) the source again.
+#[derive(Debug, Clone)]
+struct FailedLoad {
+    error: String,
+    failures: u32,
+    retry_at: Instant,
+}
+
+type LoadFlight = OnceCell<std::result::Result<ContentSet, Arc<anyhow::Error>>>;
 
 #[derive(Debug, Clone)]
 pub struct ContentManager {
     client: reqwest::Client,
     cache: Arc<RwLock<super::cache::ContentCache>>,
+    remote_validators: Arc<DashMap<String, RemoteValidator>>,
+    /// Cache keys with a background refresh in progress.
+    refreshing: Arc<DashSet<String>>,
+    /// Loads in progress, shared by concurrent cache misses for the same key.
+    in_flight: Arc<DashMap<String, Arc<LoadFlight>>>,
+    /// Cache keys whose last load failed, backed off before loading again.
+    failures: Arc<DashMap<String, FailedLoad>>,
+    /// Created on first use, since it needs a running Tokio runtime.
+    file_watcher: Arc<OnceLock<Option<FileWatcher>>>,
 }
 
 impl ContentManager {
     pub fn new() -> Self {
+        Self::with_cache_limits(&ContentCacheConfig::default())
+    }
+
+    pub fn with_cache_limits(limits: &ContentCacheConfig) -> Self {
         Self {
             client: reqwest::Client::new(),
-            cache: Arc::new(RwLock::new(super::cache::ContentCache::new())),
+            cache: Arc::new(RwLock::new(super::cache::ContentCache::with_limits(limits))),
+            remote_validators: Arc::new(DashMap::new()),
+            refreshing: Arc::new(DashSet::new()),
+            in_flight: Arc::new(DashMap::new()),
+            failures: Arc::new(DashMap::new()),
+            file_watcher: Arc::new(OnceLock::new()),
         }
     }
 
-    pub async fn get_content(&self, source: &ContentSource) -> Result<Vec<String>> {
+    pub async fn get_content(&self, source: &ContentSource) -> Result<ContentSet> {
         let cache_key = self.generate_cache_key(source);
         
         {
             let cache = self.cache.read().await;
-            if let Some(content) = cache.get(&cache_key) {
-                return Ok(content.clone());
+            if let Some((content, expires_at)) = cache.get_with_expiry(&cache_key) {
+                let remaining = expires_at.saturating_duration_since(Instant::now());
+                if source.refresh_ahead > 0 && remaining <= Duration::from_secs(source.refresh_ahead) {
+                    self.spawn_refresh(cache_key, source.clone());
+                }
+                return Ok(content);
             }
         }
 
-        let raw_content = match source.source {
-            SourceType::File => self.read_file_content(source).await?,
-            SourceType::Remote => self.fetch_remote_content(source).await?,
+        let loaded = match self.backoff_error(&cache_key) {
+            Some(e) => Err(e),
+            None => self.load_coalesced(&cache_key, source).await,
         };
+        match loaded {
+            Ok(content) => Ok(content),
+            Err(e) => {
+                let cache = self.cache.read().await;
+                match cache.get_stale(&cache_key) {
+                    Some(content) => {
+                        warn!("Serving last-known-good content for {} after reload failed: {}", cache_key, e);
+                        Ok(content)
+                    }
+                    None => Err(e),
+                }
+            }
+        }
+    }
 
-        let split_content = self.split_content(&raw_content, &source.split_by);
-        
-        {
-            let mut cache = self.cache.write().await;
-            cache.insert(cache_key, split_content.clone(), source.cache_ttl);
+    /// Loads and caches `source`, sharing a single load between all callers
+    /// that miss the cache for `cache_key` at the same time.
+    async fn load_coalesced(&self, cache_key: &str, source: &ContentSource) -> Result<ContentSet> {
+        let flight = self.in_flight.entry(cache_key.to_string()).or_default().clone();
+
+        let result = flight.get_or_init(|| async {
+            let started = Instant::now();
+            let content = match self.load_content(source).await {
+                Ok(content) => content,
+                Err(e) => {
+                    self.record_failure(cache_key, &e);
+                    return Err(Arc::new(e));
+                }
+            };
+            self.failures.remove(cache_key);
+            if let (SourceType::File, Some(path)) = (&source.source, &source.path) {
+                self.watch_file(path, cache_key);
+            }
+            self.store(cache_key.to_string(), content.clone(), source, started.elapsed()).await;
+            Ok(content)
+        }).await.clone();
+
+        self.in_flight.remove_if(cache_key, |_, current| Arc::ptr_eq(current, &flight));
+        result.map_err(|e| anyhow::anyhow!("{:#}", e))
+    }
+
+    /// The last load error for `cache_key` while it is still backed off.
+    fn backoff_error(&self, cache_key: &str) -> Option<anyhow::Error> {
+        let failed = self.failures.get(cache_key)?;
+        (Instant::now() < failed.retry_at).then(|| {
+            anyhow::anyhow!("{} (retrying in {:?})", failed.error, failed.retry_at - Instant::now())
+        })
+    }
+
+    fn record_failure(&self, cache_key: &str, error: &anyhow::Error) {
+        let mut failed = self.failures.entry(cache_key.to_string()).or_insert_with(|| FailedLoad {
+            error: String::new(),
+            failures: 0,
+            retry_at: Instant::now(),
+        });
+        failed.failures = failed.failures.saturating_add(1);
+        let backoff = FAILURE_BACKOFF_BASE
+            .saturating_mul(1 << (failed.failures - 1).min(16))
+            .min(FAILURE_BACKOFF_MAX);
+        failed.error = format!("{:#}", error);
+        failed.retry_at = Instant::now() + backoff;
+    }
+
+    /// Reloads `source` in the background, leaving the current entry in place
+    /// if the reload fails.
+    fn spawn_refresh(&self, cache_key: String, source: ContentSource) {
+        if !self.refreshing.insert(cache_key.clone()) {
+            return;
         }
 
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            match manager.load_content(&source).await {
+                Ok(content) => {
+                    debug!("Refreshed content for {} ahead of expiry", cache_key);
+                    manager.failures.remove(&cache_key);
+                    manager.store(cache_key.clone(), content, &source, started.elapsed()).await;
+                }
+                Err(e) => warn!("Background refresh of {} failed: {}", cache_key, e),
+            }
+            manager.refreshing.remove(&cache_key);
+        });
+    }
+
+    /// Invalidates `cache_key` as soon as `path` changes. Failing to watch
+    /// only means changes are picked up when `cache_ttl` expires.
+    fn watch_file(&self, path: &str, cache_key: &str) {
+        let watcher = self.file_watcher.get_or_init(|| {
+            FileWatcher::new(self.cache.clone())
+                .map_err(|e| warn!("Content file watching disabled: {}", e))
+                .ok()
+        });
+
+        if let Some(watcher) = watcher {
+            if let Err(e) = watcher.watch(path, cache_key) {
+                warn!("Failed to watch content file {}: {}", path, e);
+            }
+        }
+    }
+
+    async fn store(&self, cache_key: String, content: ContentSet, source: &ContentSource, fetch_duration: Duration) {
+        let mut cache = self.cache.write().await;
+        if !cache.insert(cache_key.clone(), content, source.cache_ttl, source.max_stale, fetch_duration) {
+            warn!("Content for {} exceeds the content cache byte budget and was not cached", cache_key);
+        }
+    }
+
+    async fn load_content(&self, source: &ContentSource) -> Result<ContentSet> {
+        let mut split_content = match source.source {
+            SourceType::File => {
+                let raw_content = self.read_file_content(source).await?;
+                self.split_content(&raw_content, source)?
+            }
+            SourceType::Remote => {
+                let raw_content = self.fetch_remote_content(source).await?;
+                self.split_content(&raw_content, source)?
+            }
+            SourceType::Inline => ContentSet {
+                values: source.values.iter().flatten().map(|value| ContentValue::from(value.as_str())).collect(),
+                weights: source.weights.clone().unwrap_or_default(),
+                secret: false,
+            },
+            SourceType::Env => {
+                let raw_content = self.read_env_content(source)?;
+                self.split_content(&raw_content, source)?
+            }
+            SourceType::Directory => ContentSet::new(self.read_directory_content(source).await?),
+            SourceType::Command => {
+                let raw_content = self.run_command_content(source).await?;
+                self.split_content(&raw_content, source)?
+            }
+            SourceType::Sqlite => ContentSet::new(self.query_sqlite_content(source).await?),
+        };
+
+        split_content.secret = source.secret;
         Ok(split_content)
     }
 
-    async fn read_file_content(&self, source: &ContentSource) -> Result<String> {
+    async fn read_file_content(&self, source: &ContentSource) -> Result<Zeroizing<String>> {
         let path = source.path.as_ref()
             .ok_or_else(|| anyhow::anyhow!("File path is required for file source"))?;
         
-        match tokio::fs::read_to_string(path).await {
-            Ok(content) => Ok(content),
-            Err(e) => {
-                error!("Failed to read file {}: {}", path, e);
-                Err(anyhow::anyhow!("Failed to read file: {}", e))
-            }
-        }
+        let Some(encryption) = &source.encryption else {
+            return match tokio::fs::read_to_string(path).await {
+                Ok(content) => Ok(Zeroizing::new(content)),
+                Err(e) => {
+                    error!("Failed to read file {}: {}", path, e);
+                    Err(anyhow::anyhow!("Failed to read file: {}", e))
+                }
+            };
+        };
+
+        let data = tokio::fs::read(path).await.map_err(|e| {
+            error!("Failed to read file {}: {}", path, e);
+            anyhow::anyhow!("Failed to read file: {}", e)
+        })?;
+        let mut plaintext = crypto::load_key(encryption)
+            .and_then(|key| crypto::decrypt(&data, &key))
+            .map_err(|e| {
+                error!("Failed to decrypt file {}: {}", path, e);
+                e
+            })?;
+
+        // Move the bytes rather than copying them, so no plaintext is left behind unzeroized.
+        String::from_utf8(std::mem::take(&mut *plaintext))
+            .map(Zeroizing::new)
+            .map_err(|e| {
+                drop(Zeroizing::new(e.into_bytes()));
+                anyhow::anyhow!("Decrypted file {} is not valid UTF-8", path)
+            })
     }
 
-    async fn fetch_remote_content(&self, source: &ContentSource) -> Result<String> {
+    async fn fetch_remote_content(&self, source: &ContentSource) -> Result<Zeroizing<String>> {
         let url = source.url.as_ref()
             .ok_or_else(|| anyhow::anyhow!("URL is required for remote source"))?;
-        
-        match self.client.get(url).send().await {
-            Ok(response) => {
-                if response.status().is_success() {
-                    match response.text().await {
-                        Ok(content) => Ok(content),
-                        Err(e) => {
-                            error!("Failed to read response body from {}: {}", url, e);
-                            Err(anyhow::anyhow!("Failed to read response: {}", e))
-                        }
+
+        let mut request = self.client.get(url)
+            .timeout(Duration::from_secs(source.timeout));
+        for (name, value) in &source.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        // The credentials sent, so validators are only reused for the same ones.
+        let mut credentials = Zeroizing::new(String::new());
+        request = match &source.auth {
+            Some(RemoteAuth::Bearer { token_env }) => {
+                let token = Zeroizing::new(read_env(token_env)?);
+                credentials.push_str(&token);
+                request.bearer_auth(token.as_str())
+            }
+            Some(RemoteAuth::Basic { username, username_env, password_env }) => {
+                let username = match (username, username_env) {
+                    (_, Some(variable)) => read_env(variable)?,
+                    (Some(username), None) => username.clone(),
+                    (None, None) => return Err(anyhow::anyhow!("Basic auth requires a username")),
+                };
+                let password = password_env.as_deref().map(read_env).transpose()?.map(Zeroizing::new);
+                credentials.push_str(&format!("{}\u{1f}{}", username, password.as_deref().map_or("", String::as_str)));
+                request.basic_auth(username, password.as_deref())
+            }
+            None => request,
+        };
+
+        let validator_key = format!(
+            "{}:{}",
+            self.generate_cache_key(source),
+            super::secret::digest(&credentials)
+        );
+        let cached = self.remote_validators.get(&validator_key).map(|entry| entry.clone());
+        let unconditional = request.try_clone();
+        if let Some(cached) = &cached {
+            if let Some(etag) = &cached.etag {
+                request = request.header(IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+
+        let mut response = send_remote(request, url).await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            match (cached, unconditional) {
+                (Some(cached), _) => {
+                    debug!("Remote content at {} not modified", url);
+                    return Ok(cached.body);
+                }
+                // Nothing to reuse (e.g. the validator was dropped), so ask for the full content.
+                (None, Some(unconditional)) => {
+                    warn!("Remote {} answered 304 Not Modified without cached content, fetching again", url);
+                    response = send_remote(unconditional.header(CACHE_CONTROL, "no-cache"), url).await?;
+                }
+                (None, None) => {}
+            }
+        }
+
+        if !response.status().is_success() {
+            error!("HTTP error {} when fetching {}", response.status(), url);
+            return Err(anyhow::anyhow!("HTTP error: {}", response.status()));
+        }
+
+        if response.content_length().is_some_and(|length| length > source.max_response_size) {
+            error!("Response from {} exceeds {} bytes", url, source.max_response_size);
+            return Err(anyhow::anyhow!("Response exceeds max_response_size of {} bytes", source.max_response_size));
+        }
+
+        let header = |name| response.headers().get(name)
+            .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+            .map(str::to_string);
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+
+        // Content-Length may be absent or wrong, so enforce the limit while reading.
+        let mut body = Zeroizing::new(Vec::new());
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    if (body.len() + chunk.len()) as u64 > source.max_response_size {
+                        error!("Response from {} exceeds {} bytes", url, source.max_response_size);
+                        return Err(anyhow::anyhow!("Response exceeds max_response_size of {} bytes", source.max_response_size));
                     }
-                } else {
-                    error!("HTTP error {} when fetching {}", response.status(), url);
-                    Err(anyhow::anyhow!("HTTP error: {}", response.status()))
+                    body.extend_from_slice(&chunk);
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Failed to read response body from {}: {}", url, e);
+                    return Err(anyhow::anyhow!("Failed to read response: {}", e));
                 }
             }
-            Err(e) => {
-                error!("Failed to fetch content from {}: {}", url, e);
-                Err(anyhow::anyhow!("Request failed: {}", e))
+        }
+
+        let content = Zeroizing::new(String::from_utf8_lossy(&body).into_owned());
+        if etag.is_some() || last_modified.is_some() {
+            self.remote_validators.insert(validator_key, RemoteValidator {
+                etag,
+                last_modified,
+                body: content.clone(),
+            });
+        } else {
+            self.remote_validators.remove(&validator_key);
+        }
+
+        Ok(content)
+    }
+
+    fn read_env_content(&self, source: &ContentSource) -> Result<Zeroizing<String>> {
+        let variable = source.variable.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Variable name is required for env source"))?;
+
+        read_env(variable).map(Zeroizing::new)
+    }
+
+    async fn read_directory_content(&self, source: &ContentSource) -> Result<Vec<String>> {
+        let path = source.path.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Directory path is required for directory source"))?;
+
+        let mut entries = tokio::fs::read_dir(path).await.map_err(|e| {
+            error!("Failed to read directory {}: {}", path, e);
+            anyhow::anyhow!("Failed to read directory: {}", e)
+        })?;
+
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if !hidden && entry.file_type().await?.is_file() {
+                files.push(entry.path());
             }
         }
+        files.sort();
+
+        let mut values = Vec::with_capacity(files.len());
+        for file in files {
+            let content = tokio::fs::read_to_string(&file).await.map(Zeroizing::new).map_err(|e| {
+                error!("Failed to read file {}: {}", file.display(), e);
+                anyhow::anyhow!("Failed to read file: {}", e)
+            })?;
+            let value = content.trim();
+            if !value.is_empty() {
+                values.push(value.to_string());
+            }
+        }
+
+        if values.is_empty() {
+            warn!("No values found in directory {}", path);
+        }
+
+        Ok(values)
+    }
+
+    async fn run_command_content(&self, source: &ContentSource) -> Result<Zeroizing<String>> {
+        let command = source.command.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Command is required for command source"))?;
+
+        let output = tokio::process::Command::new(command)
+            .args(&source.args)
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output();
+
+        match tokio::time::timeout(Duration::from_secs(source.timeout), output).await {
+            Ok(Ok(output)) if output.status.success() => {
+                let stdout = Zeroizing::new(output.stdout);
+                Ok(Zeroizing::new(String::from_utf8_lossy(&stdout).into_owned()))
+            }
+            Ok(Ok(output)) => {
+                error!("Command {} exited with {}: {}", command, output.status, String::from_utf8_lossy(&output.stderr).trim());
+                Err(anyhow::anyhow!("Command exited with {}", output.status))
+            }
+            Ok(Err(e)) => {
+                error!("Failed to run command {}: {}", command, e);
+                Err(anyhow::anyhow!("Failed to run command: {}", e))
+            }
+            Err(_) => {
+                error!("Command {} timed out after {}s", command, source.timeout);
+                Err(anyhow::anyhow!("Command timed out after {}s", source.timeout))
+            }
+        }
+    }
+
+    async fn query_sqlite_content(&self, source: &ContentSource) -> Result<Vec<String>> {
+        let path = source.path.clone()
+            .ok_or_else(|| anyhow::anyhow!("Database path is required for sqlite source"))?;
+        let query = source.query.clone()
+            .ok_or_else(|| anyhow::anyhow!("Query is required for sqlite source"))?;
+
+        tokio::task::spawn_blocking(move || -> Result<Vec<String>> {
+            let connection = rusqlite::Connection::open_with_flags(
+                &path,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+            ).map_err(|e| {
+                error!("Failed to open SQLite database {}: {}", path, e);
+                anyhow::anyhow!("Failed to open SQLite database: {}", e)
+            })?;
+
+            let mut statement = connection.prepare(&query)?;
+            let mut rows = statement.query([])?;
+            let mut values = Vec::new();
+            while let Some(row) = rows.next()? {
+                let value = match row.get_ref(0)? {
+                    ValueRef::Null => continue,
+                    ValueRef::Integer(i) => i.to_string(),
+                    ValueRef::Real(f) => f.to_string(),
+                    ValueRef::Text(text) | ValueRef::Blob(text) => String::from_utf8_lossy(text).trim().to_string(),
+                };
+                if !value.is_empty() {
+                    values.push(value);
+                }
+            }
+            Ok(values)
+        })
+        .await?
     }
 
-    fn split_content(&self, content: &str, strategy: &SplitStrategy) -> Vec<String> {
+    fn split_content(&self, content: &str, source: &ContentSource) -> Result<ContentSet> {
         let trimmed = content.trim();
         if trimmed.is_empty() {
             warn!("Content is empty after trimming");
-            return vec![];
+            return Ok(ContentSet::default());
         }
 
-        let parts: Vec<String> = match strategy {
+        let mut weights = Vec::new();
+        let parts: Vec<ContentValue> = match &source.split_by {
             SplitStrategy::Line => {
                 trimmed.lines()
-                    .map(|s| s.trim().to_string())
+                    .map(|s| ContentValue::from(s.trim()))
                     .filter(|s| !s.is_empty())
                     .collect()
             }
             SplitStrategy::Comma => {
                 trimmed.split(',')
-                    .map(|s| s.trim().to_string())
+                    .map(|s| ContentValue::from(s.trim()))
                     .filter(|s| !s.is_empty())
                     .collect()
             }
             SplitStrategy::Space => {
                 trimmed.split_whitespace()
-                    .map(|s| s.to_string())
+                    .map(ContentValue::from)
+                    .collect()
+            }
+            SplitStrategy::Delimiter => {
+                let delimiter = source.delimiter.as_deref()
+                    .ok_or_else(|| anyhow::anyhow!("'delimiter' is required for delimiter split"))?;
+                trimmed.split(delimiter)
+                    .map(|s| ContentValue::from(s.trim()))
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            }
+            SplitStrategy::Json => self.split_json(trimmed, source.json_path.as_deref())?
+                .into_iter()
+                .map(ContentValue::from)
+                .collect(),
+            SplitStrategy::Csv => {
+                let content = self.split_csv(trimmed, source)?;
+                weights = content.weights;
+                content.values
+            }
+            SplitStrategy::Regex => {
+                let pattern = source.pattern.as_deref()
+                    .ok_or_else(|| anyhow::anyhow!("'pattern' is required for regex split"))?;
+                let regex = regex::Regex::new(pattern)?;
+                regex.captures_iter(trimmed)
+                    .filter_map(|caps| caps.get(1).or_else(|| caps.get(0)))
+                    .map(|m| ContentValue::from(m.as_str().trim()))
+                    .filter(|s| !s.is_empty())
                     .collect()
             }
         };
@@ -113,28 +636,632 @@ impl ContentManager {
             warn!("No valid content found after splitting");
         }
 
-        parts
+        Ok(ContentSet {
+            values: parts,
+            weights,
+            secret: false,
+        })
+    }
+
+    /// Selects values from a JSON document: the nodes matched by `json_path`,
+    /// or the elements of a top-level array. Non-string values are kept as JSON.
+    fn split_json(&self, content: &str, json_path: Option<&str>) -> Result<Vec<String>> {
+        let document: serde_json::Value = serde_json::from_str(content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse JSON content: {}", e))?;
+
+        let nodes: Vec<&serde_json::Value> = match json_path {
+            Some(path) => serde_json_path::JsonPath::parse(path)
+                .map_err(|e| anyhow::anyhow!("Invalid JSONPath '{}': {}", path, e))?
+                .query(&document)
+                .all(),
+            None => match &document {
+                serde_json::Value::Array(items) => items.iter().collect(),
+                other => vec![other],
+            },
+        };
+
+        Ok(nodes.into_iter()
+            .filter_map(|node| match node {
+                serde_json::Value::String(s) => Some(s.trim().to_string()),
+                serde_json::Value::Null => None,
+                other => Some(other.to_string()),
+            })
+            .filter(|s| !s.is_empty())
+            .collect())
+    }
+
+    /// Reads one column from CSV content with a header row; defaults to the
+    /// first column. Weights are read from `weight_column` when set.
+    fn split_csv(&self, content: &str, source: &ContentSource) -> Result<ContentSet> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(content.as_bytes());
+
+        let headers = reader.headers()?.clone();
+        let find_column = |column: &str| {
+            headers.iter()
+                .position(|header| header == column)
+                .ok_or_else(|| anyhow::anyhow!("CSV column '{}' not found", column))
+        };
+
+        let index = match &source.column {
+            Some(column) => find_column(column)?,
+            None => 0,
+        };
+        let weight_index = source.weight_column.as_deref().map(find_column).transpose()?;
+
+        let mut content = ContentSet::default();
+        for record in reader.records() {
+            let record = record?;
+            let value = match record.get(index) {
+                Some(value) if !value.is_empty() => value.to_string(),
+                _ => continue,
+            };
+
+            if let Some(weight_index) = weight_index {
+                let weight = record.get(weight_index).unwrap_or("");
+                content.weights.push(weight.parse().unwrap_or_else(|_| {
+                    warn!("Invalid weight '{}' for CSV value, using 1", weight);
+                    1
+                }));
+            }
+            content.values.push(value.into());
+        }
+        Ok(content)
     }
 
+    /// Key for the content `source` loads: its location, plus a hash of every
+    /// setting that changes the loaded values or how they are flagged.
     fn generate_cache_key(&self, source: &ContentSource) -> String {
+        // Patterns and paths may name secrets, so the settings are only hashed.
+        let settings = Zeroizing::new(format!(
+            "{:?}",
+            (
+                (&source.split_by, &source.json_path, &source.column, &source.pattern, &source.delimiter),
+                (&source.weights, &source.weight_column, &source.selection, &source.sticky_header),
+                (&source.encryption, source.max_response_size, source.secret),
+            )
+        ));
+        format!("{}#{}", self.source_location(source), &super::secret::digest(&settings)[..16])
+    }
+
+    fn source_location(&self, source: &ContentSource) -> String {
         match source.source {
             SourceType::File => {
                 format!("file:{}", source.path.as_ref().unwrap_or(&"unknown".to_string()))
             }
             SourceType::Remote => {
-                format!("remote:{}", source.url.as_ref().unwrap_or(&"unknown".to_string()))
+                let url = source.url.as_deref().unwrap_or("unknown");
+                if source.headers.is_empty() && source.auth.is_none() {
+                    return format!("remote:{}", url);
+                }
+                // Headers may hold secrets, so they only go into the key hashed.
+                let mut headers: Vec<_> = source.headers.iter().collect();
+                headers.sort();
+                let request = Zeroizing::new(format!("{:?}{:?}", headers, source.auth));
+                format!("remote:{}#{}", url, &super::secret::digest(&request)[..16])
+            }
+            SourceType::Inline => {
+                let values = source.values.as_deref().unwrap_or_default().join("\u{1f}");
+                if source.secret {
+                    format!("inline:sha256:{}", super::secret::digest(&values))
+                } else {
+                    format!("inline:{}", values)
+                }
+            }
+            SourceType::Env => {
+                format!("env:{}", source.variable.as_ref().unwrap_or(&"unknown".to_string()))
+            }
+            SourceType::Directory => {
+                format!("dir:{}", source.path.as_ref().unwrap_or(&"unknown".to_string()))
+            }
+            SourceType::Command => {
+                let mut command = vec![source.command.clone().unwrap_or_else(|| "unknown".to_string())];
+                command.extend(source.args.iter().cloned());
+                format!("command:{}", command.join(" "))
+            }
+            SourceType::Sqlite => {
+                format!(
+                    "sqlite:{}:{}",
+                    source.path.as_ref().unwrap_or(&"unknown".to_string()),
+                    source.query.as_ref().unwrap_or(&"unknown".to_string())
+                )
             }
         }
     }
 
     pub async fn clear_cache(&self) {
+        self.failures.clear();
         let mut cache = self.cache.write().await;
         cache.clear();
     }
 
     pub async fn remove_from_cache(&self, source: &ContentSource) {
         let cache_key = self.generate_cache_key(source);
+        self.failures.remove(&cache_key);
         let mut cache = self.cache.write().await;
         cache.remove(&cache_key);
     }
-}
\ No newline at end of file
+
+    /// Reloads `source` now, replacing its cache entry. On failure the
+    /// existing entry is left untouched.
+    pub async fn refresh(&self, source: &ContentSource) -> Result<ContentSet> {
+        let cache_key = self.generate_cache_key(source);
+        self.load_coalesced(&cache_key, source).await
+    }
+
+    pub async fn cache_stats(&self) -> CacheStatsSnapshot {
+        self.cache.read().await.stats()
+    }
+
+    pub async fn cache_entry_info(&self, source: &ContentSource) -> Option<CacheEntryInfo> {
+        let cache_key = self.generate_cache_key(source);
+        self.cache.read().await.entry_info(&cache_key)
+    }
+
+    /// The cached content of `source`, if any, without loading it.
+    pub async fn cached_content(&self, source: &ContentSource) -> Option<ContentSet> {
+        let cache_key = self.generate_cache_key(source);
+        self.cache.read().await.peek(&cache_key)
+    }
+
+    /// Drops entries that are past both their TTL and their staleness allowance.
+    pub async fn cleanup_expired(&self) -> usize {
+        self.cache.write().await.cleanup_expired()
+    }
+}
+
+impl Default for ContentManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn send_remote(request: reqwest::RequestBuilder, url: &str) -> Result<reqwest::Response> {
+    request.send().await.map_err(|e| {
+        error!("Failed to fetch content from {}: {}", url, e);
+        anyhow::anyhow!("Request failed: {}", e)
+    })
+}
+
+fn read_env(variable: &str) -> Result<String> {
+    std::env::var(variable).map_err(|e| {
+        error!("Failed to read environment variable {}: {}", variable, e);
+        anyhow::anyhow!("Failed to read environment variable {}: {}", variable, e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(source_type: SourceType) -> ContentSource {
+        let mut source: ContentSource = toml::from_str("source = \"inline\"\nmax_response_size = 1024").unwrap();
+        source.source = source_type;
+        source
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ultiproxy-{}-{}", name, uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn test_inline_source() {
+        let manager = ContentManager::new();
+        let mut inline = source(SourceType::Inline);
+        inline.values = Some(vec!["a".to_string(), "b".to_string()]);
+
+        assert_eq!(manager.get_content(&inline).await.unwrap().values, vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_env_source() {
+        let manager = ContentManager::new();
+        // Cargo sets this for test binaries, so nothing needs to be changed in the environment.
+        let mut env = source(SourceType::Env);
+        env.variable = Some("CARGO_PKG_NAME".to_string());
+        assert_eq!(manager.get_content(&env).await.unwrap().values, vec![env!("CARGO_PKG_NAME")]);
+
+        env.variable = Some(format!("ULTIPROXY_UNSET_{}", uuid::Uuid::new_v4().simple()));
+        assert!(manager.get_content(&env).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_secret_source() {
+        let manager = ContentManager::new();
+        let mut inline = source(SourceType::Inline);
+        inline.values = Some(vec!["sk-live-abcdef123456".to_string()]);
+        inline.secret = true;
+
+        assert!(manager.get_content(&inline).await.unwrap().secret);
+        assert!(!manager.generate_cache_key(&inline).contains("sk-live"));
+
+        // The same values without `secret` are cached apart, so neither
+        // source sees the other's flag.
+        let mut plain = inline.clone();
+        plain.secret = false;
+        assert!(!manager.get_content(&plain).await.unwrap().secret);
+        assert!(manager.get_content(&inline).await.unwrap().secret);
+
+        // Cache hits share the cached value rather than copying it.
+        let (first, second) = (manager.get_content(&inline).await.unwrap(), manager.get_content(&inline).await.unwrap());
+        assert!(std::ptr::eq(first.values[0].as_str(), second.values[0].as_str()));
+        assert!(!format!("{:?}", first).contains("sk-live"));
+
+        let redacted = inline.redacted();
+        assert!(!redacted.values.as_ref().unwrap()[0].contains("sk-live"));
+    }
+
+    #[test]
+    fn test_redacted_rule_round_trip() {
+        let rule: crate::config::ForwardingRule = toml::from_str(r#"
+            name = "api"
+            path = "/api/**"
+            target_urls = ["http://localhost:1"]
+            load_balancing = "round_robin"
+            [header_replacements.Authorization]
+            source = "inline"
+            values = ["sk-live-abcdef123456", "sk-live-987654fedcba"]
+            secret = true
+        "#).unwrap();
+        let values = |rule: &crate::config::ForwardingRule| rule.header_replacements["Authorization"].values.clone();
+
+        let mut edited = rule.redacted();
+        edited.header_replacements.get_mut("Authorization").unwrap().values.as_mut().unwrap().reverse();
+        edited.restore_secrets(Some(&rule)).unwrap();
+        assert_eq!(values(&edited), Some(vec!["sk-live-987654fedcba".to_string(), "sk-live-abcdef123456".to_string()]));
+
+        // A fingerprint can only stand for a value of the rule it replaces.
+        assert!(rule.redacted().restore_secrets(None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_split_settings_are_cached_separately() {
+        let path = temp_path("inventory.json");
+        std::fs::write(&path, r#"[{"key": "k1", "name": "primary"}, {"key": "k2", "name": "backup"}]"#).unwrap();
+
+        let manager = ContentManager::new();
+        let mut keys = source(SourceType::File);
+        keys.path = Some(path.to_string_lossy().into_owned());
+        keys.split_by = SplitStrategy::Json;
+        keys.json_path = Some("$[*].key".to_string());
+        let mut names = keys.clone();
+        names.json_path = Some("$[*].name".to_string());
+
+        assert_eq!(manager.get_content(&keys).await.unwrap().values, vec!["k1", "k2"]);
+        assert_eq!(manager.get_content(&names).await.unwrap().values, vec!["primary", "backup"]);
+
+        let csv_path = temp_path("inventory.csv");
+        std::fs::write(&csv_path, "key,name\nk1,primary\n").unwrap();
+        let mut key_column = source(SourceType::File);
+        key_column.path = Some(csv_path.to_string_lossy().into_owned());
+        key_column.split_by = SplitStrategy::Csv;
+        key_column.column = Some("key".to_string());
+        let mut name_column = key_column.clone();
+        name_column.column = Some("name".to_string());
+
+        assert_eq!(manager.get_content(&key_column).await.unwrap().values, vec!["k1"]);
+        assert_eq!(manager.get_content(&name_column).await.unwrap().values, vec!["primary"]);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_file_source() {
+        use base64::Engine;
+
+        let key = [3u8; 32];
+        let key_path = temp_path("key");
+        std::fs::write(&key_path, base64::engine::general_purpose::STANDARD.encode(key)).unwrap();
+        let path = temp_path("keys.enc");
+        std::fs::write(&path, crypto::encrypt(b"k1\nk2", &key).unwrap()).unwrap();
+
+        let manager = ContentManager::new();
+        let mut file = source(SourceType::File);
+        file.path = Some(path.to_string_lossy().into_owned());
+        file.encryption = Some(crate::config::EncryptionConfig {
+            key_env: None,
+            key_file: Some(key_path.to_string_lossy().into_owned()),
+        });
+
+        assert_eq!(manager.get_content(&file).await.unwrap().values, vec!["k1", "k2"]);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(key_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_directory_source() {
+        let dir = temp_path("dir");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("b.key"), "second\n").unwrap();
+        std::fs::write(dir.join("a.key"), "first").unwrap();
+        std::fs::write(dir.join(".hidden"), "ignored").unwrap();
+
+        let manager = ContentManager::new();
+        let mut directory = source(SourceType::Directory);
+        directory.path = Some(dir.to_string_lossy().into_owned());
+
+        assert_eq!(manager.get_content(&directory).await.unwrap().values, vec!["first", "second"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_source() {
+        let manager = ContentManager::new();
+        let mut command = source(SourceType::Command);
+        command.command = Some("echo".to_string());
+        command.args = vec!["k1,k2".to_string()];
+        command.split_by = SplitStrategy::Comma;
+
+        assert_eq!(manager.get_content(&command).await.unwrap().values, vec!["k1", "k2"]);
+
+        command.command = Some("false".to_string());
+        command.args = Vec::new();
+        assert!(ContentManager::new().get_content(&command).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_source() {
+        let db = temp_path("db.sqlite");
+        {
+            let connection = rusqlite::Connection::open(&db).unwrap();
+            connection.execute_batch(
+                "CREATE TABLE keys (value TEXT, active INTEGER);
+                 INSERT INTO keys VALUES ('k1', 1), ('k2', 0), ('k3', 1);",
+            ).unwrap();
+        }
+
+        let manager = ContentManager::new();
+        let mut sqlite = source(SourceType::Sqlite);
+        sqlite.path = Some(db.to_string_lossy().into_owned());
+        sqlite.query = Some("SELECT value FROM keys WHERE active = 1 ORDER BY value".to_string());
+
+        assert_eq!(manager.get_content(&sqlite).await.unwrap().values, vec!["k1", "k3"]);
+        std::fs::remove_file(db).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_change_invalidates_cache() {
+        let path = temp_path("watched.txt");
+        std::fs::write(&path, "k1").unwrap();
+
+        let manager = ContentManager::new();
+        let mut file = source(SourceType::File);
+        file.path = Some(path.to_string_lossy().into_owned());
+
+        assert_eq!(manager.get_content(&file).await.unwrap().values, vec!["k1"]);
+        std::fs::write(&path, "k2").unwrap();
+
+        let mut values = Vec::new();
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            values = manager.get_content(&file).await.unwrap().values;
+            if values == vec!["k2"] {
+                break;
+            }
+        }
+        assert_eq!(values, vec!["k2"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_share_one_fetch() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().route("/keys", axum::routing::get(move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                "k1\nk2"
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let manager = ContentManager::new();
+        let mut remote = source(SourceType::Remote);
+        remote.url = Some(format!("http://{}/keys", address));
+
+        let results = futures::future::join_all((0..100).map(|_| manager.get_content(&remote))).await;
+        assert!(results.iter().all(|r| r.as_ref().unwrap().values == vec!["k1", "k2"]));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_last_known_good_after_failure() {
+        let path = temp_path("lkg.txt");
+        std::fs::write(&path, "k1\nk2").unwrap();
+
+        let manager = ContentManager::new();
+        let mut file = source(SourceType::File);
+        file.path = Some(path.to_string_lossy().into_owned());
+        file.cache_ttl = 0;
+
+        assert_eq!(manager.get_content(&file).await.unwrap().values, vec!["k1", "k2"]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(manager.get_content(&file).await.unwrap().values, vec!["k1", "k2"]);
+
+        file.max_stale = 0;
+        let manager = ContentManager::new();
+        std::fs::write(&path, "k1").unwrap();
+        manager.get_content(&file).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(manager.get_content(&file).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failing_source_is_backed_off() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().route("/keys", axum::routing::get(move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let manager = ContentManager::new();
+        let mut remote = source(SourceType::Remote);
+        remote.url = Some(format!("http://{}/keys", address));
+
+        for _ in 0..5 {
+            assert!(manager.get_content(&remote).await.is_err());
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(FAILURE_BACKOFF_BASE).await;
+        assert!(manager.get_content(&remote).await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        // An explicit refresh is not held back.
+        assert!(manager.refresh(&remote).await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_ahead_serves_cached_content() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().route("/keys", axum::routing::get(move || {
+            let counter = counter.clone();
+            async move { format!("k{}", counter.fetch_add(1, Ordering::SeqCst) + 1) }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let manager = ContentManager::new();
+        let mut remote = source(SourceType::Remote);
+        remote.url = Some(format!("http://{}/keys", address));
+        remote.cache_ttl = 60;
+        remote.refresh_ahead = 60;
+
+        // Within the refresh window, the cached content is served while it is reloaded.
+        assert_eq!(manager.get_content(&remote).await.unwrap().values, vec!["k1"]);
+        assert_eq!(manager.get_content(&remote).await.unwrap().values, vec!["k1"]);
+        for _ in 0..50 {
+            if manager.get_content(&remote).await.unwrap().values != vec!["k1"] {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("content was not refreshed ahead of expiry");
+    }
+
+    #[tokio::test]
+    async fn test_remote_source_revalidation_and_limits() {
+        use axum::http::{header, HeaderMap, StatusCode};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let downloads = Arc::new(AtomicUsize::new(0));
+        let counter = downloads.clone();
+        let app = axum::Router::new()
+            .route("/keys", axum::routing::get(move |headers: HeaderMap| {
+                let counter = counter.clone();
+                async move {
+                    if headers.get(header::AUTHORIZATION).is_none_or(|v| v != concat!("Bearer ", env!("CARGO_PKG_NAME"))) {
+                        return (StatusCode::UNAUTHORIZED, HeaderMap::new(), String::new());
+                    }
+                    if headers.get(header::IF_NONE_MATCH).is_some_and(|v| v == "\"v1\"") {
+                        return (StatusCode::NOT_MODIFIED, HeaderMap::new(), String::new());
+                    }
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let mut response_headers = HeaderMap::new();
+                    response_headers.insert(header::ETAG, "\"v1\"".parse().unwrap());
+                    (StatusCode::OK, response_headers, "k1\nk2".to_string())
+                }
+            }))
+            .route("/large", axum::routing::get(|| async { "x".repeat(2048) }))
+            // Answers 304 unless revalidation is explicitly bypassed.
+            .route("/stale", axum::routing::get(|headers: HeaderMap| async move {
+                if headers.get(header::CACHE_CONTROL).is_some_and(|v| v == "no-cache") {
+                    (StatusCode::OK, "fresh".to_string())
+                } else {
+                    (StatusCode::NOT_MODIFIED, String::new())
+                }
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let manager = ContentManager::new();
+        let mut remote = source(SourceType::Remote);
+        remote.url = Some(format!("http://{}/keys", address));
+        remote.auth = Some(RemoteAuth::Bearer { token_env: "CARGO_PKG_NAME".to_string() });
+
+        assert_eq!(manager.fetch_remote_content(&remote).await.unwrap().as_str(), "k1\nk2");
+        assert_eq!(manager.fetch_remote_content(&remote).await.unwrap().as_str(), "k1\nk2");
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+
+        // Validators cached for one set of credentials are not reused without them.
+        remote.auth = None;
+        assert!(manager.fetch_remote_content(&remote).await.is_err());
+
+        remote.url = Some(format!("http://{}/stale", address));
+        assert_eq!(manager.fetch_remote_content(&remote).await.unwrap().as_str(), "fresh");
+
+        remote.url = Some(format!("http://{}/large", address));
+        assert!(manager.fetch_remote_content(&remote).await.is_err());
+        remote.max_response_size = 4096;
+        assert_eq!(manager.fetch_remote_content(&remote).await.unwrap().len(), 2048);
+    }
+
+    #[test]
+    fn test_split_json() {
+        let manager = ContentManager::new();
+        let mut json = source(SourceType::File);
+        json.split_by = SplitStrategy::Json;
+        assert_eq!(manager.split_content(r#"["a", "b", 3]"#, &json).unwrap().values, vec!["a", "b", "3"]);
+
+        json.json_path = Some("$.keys[?@.active == true].value".to_string());
+        let inventory = r#"{"keys": [{"value": "k1", "active": true}, {"value": "k2", "active": false}, {"value": "k3", "active": true}]}"#;
+        assert_eq!(manager.split_content(inventory, &json).unwrap().values, vec!["k1", "k3"]);
+    }
+
+    #[test]
+    fn test_split_csv() {
+        let manager = ContentManager::new();
+        let mut csv = source(SourceType::File);
+        csv.split_by = SplitStrategy::Csv;
+        let content = "name,key\nprimary,k1\nbackup, k2\n";
+        assert_eq!(manager.split_content(content, &csv).unwrap().values, vec!["primary", "backup"]);
+
+        csv.column = Some("key".to_string());
+        assert_eq!(manager.split_content(content, &csv).unwrap().values, vec!["k1", "k2"]);
+
+        csv.weight_column = Some("weight".to_string());
+        let weighted = "key,weight\nk1,3\nk2,1\n";
+        let weighted_content = manager.split_content(weighted, &csv).unwrap();
+        assert_eq!(weighted_content.values, vec!["k1", "k2"]);
+        assert_eq!(weighted_content.weights, vec![3, 1]);
+
+        csv.column = Some("missing".to_string());
+        assert!(manager.split_content(content, &csv).is_err());
+    }
+
+    #[test]
+    fn test_split_regex_and_delimiter() {
+        let manager = ContentManager::new();
+        let mut regex = source(SourceType::File);
+        regex.split_by = SplitStrategy::Regex;
+        regex.pattern = Some(r"key=(\w+)".to_string());
+        assert_eq!(manager.split_content("key=a; other=b; key=c", &regex).unwrap().values, vec!["a", "c"]);
+
+        let mut delimiter = source(SourceType::File);
+        delimiter.split_by = SplitStrategy::Delimiter;
+        delimiter.delimiter = Some("||".to_string());
+        assert_eq!(manager.split_content("a || b||c", &delimiter).unwrap().values, vec!["a", "b", "c"]);
+    }
+}
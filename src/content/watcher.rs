@@ -0,0 +1,87 @@
+use super::cache::ContentCache;
+use dashmap::{DashMap, DashSet};
+use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, RwLock};
+use tracing::{error, info};
+
+/// Watches the files behind `file` content sources and expires their cache
+/// entries as soon as they change, so the next request reloads them.
+///
+/// Parent directories are watched rather than the files themselves, so
+/// editors that replace a file by renaming over it are noticed too.
+#[derive(Debug)]
+pub struct FileWatcher {
+    watcher: Mutex<RecommendedWatcher>,
+    watched_dirs: DashSet<PathBuf>,
+    cache_keys: Arc<DashMap<PathBuf, HashSet<String>>>,
+}
+
+impl FileWatcher {
+    pub fn new(cache: Arc<RwLock<ContentCache>>) -> anyhow::Result<Self> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let watcher = RecommendedWatcher::new(
+            move |res: Result<Event, notify::Error>| {
+                match res {
+                    Ok(event) => {
+                        // The receiver only goes away when the watcher is dropped.
+                        let _ = tx.send(event);
+                    }
+                    Err(e) => error!("Content file watch error: {}", e),
+                }
+            },
+            Config::default(),
+        )?;
+
+        let cache_keys: Arc<DashMap<PathBuf, HashSet<String>>> = Arc::new(DashMap::new());
+        let task_keys = cache_keys.clone();
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if matches!(event.kind, EventKind::Access(_)) {
+                    continue;
+                }
+
+                let keys: Vec<String> = event.paths.iter()
+                    .filter_map(|path| task_keys.get(path))
+                    .flat_map(|keys| keys.iter().cloned().collect::<Vec<_>>())
+                    .collect();
+                if keys.is_empty() {
+                    continue;
+                }
+
+                let mut cache = cache.write().await;
+                for key in keys {
+                    info!("Content file for {} changed, invalidating cache", key);
+                    cache.expire(&key);
+                }
+            }
+        });
+
+        Ok(Self {
+            watcher: Mutex::new(watcher),
+            watched_dirs: DashSet::new(),
+            cache_keys,
+        })
+    }
+
+    /// Starts watching `path`, expiring `cache_key` whenever it changes.
+    pub fn watch(&self, path: &str, cache_key: &str) -> anyhow::Result<()> {
+        let path = std::fs::canonicalize(path)?;
+        if self.cache_keys.get(&path).is_some_and(|keys| keys.contains(cache_key)) {
+            return Ok(());
+        }
+
+        let dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        if !self.watched_dirs.contains(&dir) {
+            let mut watcher = self.watcher.lock().unwrap_or_else(|e| e.into_inner());
+            watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+            self.watched_dirs.insert(dir);
+        }
+
+        self.cache_keys.entry(path).or_default().insert(cache_key.to_string());
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@ mod api;
 mod config;
 mod content;
 mod proxy;
+mod util;
 
 use axum::{
     extract::Request,
@@ -10,17 +11,19 @@ use axum::{
     routing::{any, get},
     Router,
 };
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use config::{Config, ConfigWatcher};
 use proxy::ProxyEngine;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
 use tower::ServiceBuilder;
 use tower_http::{
     cors::CorsLayer,
     trace::TraceLayer,
 };
-use tracing::info;
+use tracing::{debug, info};
 
 #[derive(Parser, Debug)]
 #[command(name = "ultiproxy")]
@@ -28,12 +31,46 @@ use tracing::info;
 struct Args {
     #[arg(short, long, default_value = "config/ultiproxy.toml")]
     config: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Encrypt a content source file for use with `encryption`
+    Encrypt(CryptoArgs),
+    /// Decrypt an encrypted content source file
+    Decrypt(CryptoArgs),
+    /// Generate a base64-encoded key for `encryption`
+    GenerateKey(GenerateKeyArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct CryptoArgs {
+    input: String,
+    #[arg(short, long)]
+    output: String,
+    /// Environment variable holding the base64-encoded 32-byte key
+    #[arg(long, required_unless_present = "key_file", conflicts_with = "key_file")]
+    key_env: Option<String>,
+    /// File holding the base64-encoded 32-byte key
+    #[arg(long)]
+    key_file: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct GenerateKeyArgs {
+    /// File to write the key to, created with owner-only permissions; printed if omitted
+    #[arg(short, long)]
+    output: Option<String>,
 }
 
 #[derive(Clone)]
 struct AppState {
     proxy_engine: Arc<ProxyEngine>,
     config: Arc<tokio::sync::RwLock<Config>>,
+    /// Configuration and rule changes, forwarded to WebSocket clients.
+    events: tokio::sync::broadcast::Sender<api::types::WebSocketEvent>,
 }
 
 #[tokio::main]
@@ -41,6 +78,9 @@ async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
     let args = Args::parse();
+    if let Some(command) = args.command {
+        return run_command(command);
+    }
     
     let config = Config::from_file(&args.config)?;
     config.validate()?;
@@ -48,15 +88,40 @@ async fn main() -> anyhow::Result<()> {
     info!("Starting UltiProxy server...");
     info!("Configuration loaded from: {}", args.config);
 
-    let proxy_engine = Arc::new(ProxyEngine::new());
+    let proxy_engine = Arc::new(ProxyEngine::with_content_cache(&config.content_cache));
     proxy_engine.update_rules(config.forwarding_rules.clone()).await?;
 
     let state = AppState {
         proxy_engine: proxy_engine.clone(),
         config: Arc::new(tokio::sync::RwLock::new(config.clone())),
+        events: tokio::sync::broadcast::channel(16).0,
     };
 
     let (_watcher, _config_tx) = ConfigWatcher::new(&args.config)?;
+
+    let cleanup_engine = proxy_engine.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            let removed = cleanup_engine.cleanup_expired_content().await;
+            if removed > 0 {
+                debug!("Removed {} expired content cache entries", removed);
+            }
+            let removed = cleanup_engine.cleanup_expired_responses().await;
+            if removed > 0 {
+                debug!("Removed {} expired cached responses", removed);
+            }
+            let removed = cleanup_engine.cleanup_rate_limits();
+            if removed > 0 {
+                debug!("Removed {} idle rate limit buckets", removed);
+            }
+            let removed = cleanup_engine.cleanup_quota_usage().await;
+            if removed > 0 {
+                debug!("Removed {} stale quota usage entries", removed);
+            }
+        }
+    });
     
     let app = Router::new()
         .route("/health", get(health_check))
@@ -76,13 +141,66 @@ async fn main() -> anyhow::Result<()> {
 
     info!("Server listening on {}:{}", config.server.host, config.server.port);
 
-    axum::serve(listener, app)
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(shutdown_signal())
         .await?;
 
     Ok(())
 }
 
+fn run_command(command: Command) -> anyhow::Result<()> {
+    let (args, encrypting) = match command {
+        Command::Encrypt(args) => (args, true),
+        Command::Decrypt(args) => (args, false),
+        Command::GenerateKey(args) => {
+            let key = content::crypto::generate_key();
+            match args.output {
+                Some(path) => {
+                    write_private(&path, key.as_bytes())?;
+                    info!("Wrote a new key to {}", path);
+                }
+                None => println!("{}", key.as_str()),
+            }
+            return Ok(());
+        }
+    };
+
+    let key = content::crypto::load_key(&config::EncryptionConfig {
+        key_env: args.key_env,
+        key_file: args.key_file,
+    })?;
+    let input = std::fs::read(&args.input)?;
+
+    if encrypting {
+        std::fs::write(&args.output, content::crypto::encrypt(&input, &key)?)?;
+        info!("Encrypted {} to {}", args.input, args.output);
+    } else {
+        write_private(&args.output, &content::crypto::decrypt(&input, &key)?)?;
+        info!("Decrypted {} to {}", args.input, args.output);
+    }
+    Ok(())
+}
+
+/// Writes `data` to a file only its owner can read, since it holds a secret.
+fn write_private(path: &str, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // `mode` only applies to new files.
+        if let Ok(metadata) = std::fs::metadata(path) {
+            let mut permissions = metadata.permissions();
+            permissions.set_mode(0o600);
+            std::fs::set_permissions(path, permissions)?;
+        }
+    }
+    options.open(path)?.write_all(data)
+}
+
 async fn proxy_handler(
     axum::extract::State(state): axum::extract::State<AppState>,
     request: Request,
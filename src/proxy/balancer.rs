@@ -0,0 +1,233 @@
+use crate::config::{ConsistentHashConfig, LoadBalancingStrategy};
+use crate::proxy::hashing::HashTable;
+use dashmap::DashMap;
+use rand::Rng;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Weight of the newest sample in the response time average.
+const EWMA_ALPHA: f64 = 0.3;
+
+/// Response time sample recorded for a failed request, so that a target that
+/// fails fast does not look like the fastest one.
+const FAILURE_PENALTY: Duration = Duration::from_secs(10);
+
+/// Picks targets for the strategies other than round robin, from in-flight
+/// counts, an exponentially weighted moving average of each target's
+/// response time, or a hash table per rule.
+#[derive(Debug, Default)]
+pub struct LoadBalancer {
+    /// Per-rule rotation, so ties do not always go to the first target.
+    offsets: DashMap<String, AtomicUsize>,
+    latencies: DashMap<(String, String), f64>,
+    hash_tables: DashMap<String, Arc<HashTable>>,
+}
+
+impl LoadBalancer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn select(
+        &self,
+        rule: &str,
+        strategy: LoadBalancingStrategy,
+        targets: &[String],
+        in_flight: impl Fn(&str) -> usize,
+    ) -> Option<String> {
+        if targets.is_empty() {
+            return None;
+        }
+
+        let index = match strategy {
+            LoadBalancingStrategy::Random => rand::thread_rng().gen_range(0..targets.len()),
+            LoadBalancingStrategy::LeastConnections => {
+                self.min_by_score(rule, targets, |target| in_flight(target) as f64)
+            }
+            LoadBalancingStrategy::LeastResponseTime => {
+                let unobserved = self.average_latency_ms(rule, targets);
+                self.min_by_score(rule, targets, |target| {
+                    self.latency_ms(rule, target).unwrap_or(unobserved) * (in_flight(target) + 1) as f64
+                })
+            }
+            LoadBalancingStrategy::PowerOfTwoChoices => {
+                let mut rng = rand::thread_rng();
+                let first = rng.gen_range(0..targets.len());
+                let second = if targets.len() > 1 {
+                    (first + rng.gen_range(1..targets.len())) % targets.len()
+                } else {
+                    first
+                };
+                let unobserved = self.average_latency_ms(rule, targets);
+                let load = |index: usize| {
+                    let target = &targets[index];
+                    (in_flight(target), self.latency_ms(rule, target).unwrap_or(unobserved))
+                };
+                if load(second) < load(first) { second } else { first }
+            }
+            // Hashing needs a request key and goes through `select_hashed`.
+            LoadBalancingStrategy::RoundRobin
+            | LoadBalancingStrategy::WeightedRoundRobin
+            | LoadBalancingStrategy::ConsistentHash => self.next_offset(rule, targets.len()),
+        };
+
+        targets.get(index).cloned()
+    }
+
+    /// The target `key` maps to on the rule's hash table, built on first use
+    /// and rebuilt when the targets change.
+    pub fn select_hashed(&self, rule: &str, targets: &[String], config: &ConsistentHashConfig, key: &str) -> Option<String> {
+        let table = {
+            let mut table = self.hash_tables
+                .entry(rule.to_string())
+                .or_insert_with(|| Arc::new(HashTable::new(targets, config)));
+            if !table.is_current(targets, config) {
+                *table = Arc::new(HashTable::new(targets, config));
+            }
+            table.clone()
+        };
+        table.get(key).and_then(|index| targets.get(index).cloned())
+    }
+
+    /// Folds a response time observed for `target` into its average.
+    pub fn record_latency(&self, rule: &str, target: &str, latency: Duration) {
+        let sample = latency.as_secs_f64() * 1000.0;
+        self.latencies
+            .entry((rule.to_string(), target.to_string()))
+            .and_modify(|average| *average = EWMA_ALPHA * sample + (1.0 - EWMA_ALPHA) * *average)
+            .or_insert(sample);
+    }
+
+    /// Records a failed or timed out request to `target`, which counts as at
+    /// least `FAILURE_PENALTY`.
+    pub fn record_failure(&self, rule: &str, target: &str, elapsed: Duration) {
+        self.record_latency(rule, target, elapsed.max(FAILURE_PENALTY));
+    }
+
+    /// Average response time of `target`, in milliseconds.
+    pub fn latency_ms(&self, rule: &str, target: &str) -> Option<f64> {
+        self.latencies.get(&(rule.to_string(), target.to_string())).map(|average| *average)
+    }
+
+    /// Mean of the observed averages of `targets`, which is what targets
+    /// without observations are assumed to take.
+    fn average_latency_ms(&self, rule: &str, targets: &[String]) -> f64 {
+        let observed: Vec<f64> = targets.iter().filter_map(|target| self.latency_ms(rule, target)).collect();
+        if observed.is_empty() {
+            return 0.0;
+        }
+        observed.iter().sum::<f64>() / observed.len() as f64
+    }
+
+    /// Forgets observations, e.g. after the rules changed.
+    pub fn clear(&self) {
+        self.offsets.clear();
+        self.latencies.clear();
+        self.hash_tables.clear();
+    }
+
+    /// Index of the lowest-scoring target, scanning from a rotating offset.
+    fn min_by_score(&self, rule: &str, targets: &[String], score: impl Fn(&str) -> f64) -> usize {
+        let start = self.next_offset(rule, targets.len());
+        (0..targets.len())
+            .map(|i| (start + i) % targets.len())
+            .min_by(|a, b| score(&targets[*a]).total_cmp(&score(&targets[*b])))
+            .unwrap_or(start)
+    }
+
+    fn next_offset(&self, rule: &str, len: usize) -> usize {
+        let offsets = self.offsets.entry(rule.to_string()).or_default();
+        offsets.fetch_add(1, Ordering::Relaxed) % len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn targets() -> Vec<String> {
+        vec!["http://a".to_string(), "http://b".to_string(), "http://c".to_string()]
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let balancer = LoadBalancer::new();
+        let in_flight = HashMap::from([("http://a", 3), ("http://b", 1), ("http://c", 2)]);
+
+        for _ in 0..5 {
+            let target = balancer.select("r", LoadBalancingStrategy::LeastConnections, &targets(), |t| in_flight[t]);
+            assert_eq!(target.as_deref(), Some("http://b"));
+        }
+
+        // Ties rotate instead of always picking the first target.
+        let picked: std::collections::HashSet<_> = (0..3)
+            .filter_map(|_| balancer.select("r", LoadBalancingStrategy::LeastConnections, &targets(), |_| 0))
+            .collect();
+        assert_eq!(picked.len(), 3);
+    }
+
+    #[test]
+    fn test_least_response_time() {
+        let balancer = LoadBalancer::new();
+        balancer.record_latency("r", "http://a", Duration::from_millis(100));
+        balancer.record_latency("r", "http://b", Duration::from_millis(10));
+        balancer.record_latency("r", "http://c", Duration::from_millis(50));
+
+        let target = balancer.select("r", LoadBalancingStrategy::LeastResponseTime, &targets(), |_| 0);
+        assert_eq!(target.as_deref(), Some("http://b"));
+
+        // Load counts as well: 10ms * 10 in flight scores worse than 50ms idle.
+        let target = balancer.select("r", LoadBalancingStrategy::LeastResponseTime, &targets(), |t| {
+            if t == "http://b" { 9 } else { 0 }
+        });
+        assert_eq!(target.as_deref(), Some("http://c"));
+
+        balancer.record_latency("r", "http://b", Duration::from_millis(110));
+        let average = balancer.latency_ms("r", "http://b").unwrap();
+        assert!((average - 40.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_failing_target_loses_least_response_time() {
+        let balancer = LoadBalancer::new();
+        let strategy = LoadBalancingStrategy::LeastResponseTime;
+
+        // `a` refuses connections at once, `b` answers in 50ms, `c` is unobserved.
+        balancer.record_failure("r", "http://a", Duration::from_millis(1));
+        balancer.record_latency("r", "http://b", Duration::from_millis(50));
+        assert!(balancer.latency_ms("r", "http://a").unwrap() >= 10_000.0);
+
+        let picked: Vec<_> = (0..6).filter_map(|_| balancer.select("r", strategy, &targets(), |_| 0)).collect();
+        assert!(picked.iter().all(|target| target == "http://b"));
+    }
+
+    #[test]
+    fn test_select_hashed_is_stable() {
+        let balancer = LoadBalancer::new();
+        let config = ConsistentHashConfig::default();
+
+        let first = balancer.select_hashed("r", &targets(), &config, "user-1");
+        assert!(first.is_some());
+        for _ in 0..5 {
+            assert_eq!(balancer.select_hashed("r", &targets(), &config, "user-1"), first);
+        }
+        assert_eq!(balancer.select_hashed("r", &[], &config, "user-1"), None);
+    }
+
+    #[test]
+    fn test_power_of_two_choices_avoids_most_loaded() {
+        let balancer = LoadBalancer::new();
+        let in_flight = HashMap::from([("http://a", 0), ("http://b", 5), ("http://c", 10)]);
+
+        for _ in 0..50 {
+            let target = balancer.select("r", LoadBalancingStrategy::PowerOfTwoChoices, &targets(), |t| in_flight[t]);
+            assert_ne!(target.as_deref(), Some("http://c"));
+        }
+        assert_eq!(
+            balancer.select("r", LoadBalancingStrategy::PowerOfTwoChoices, &targets()[..1], |_| 0).as_deref(),
+            Some("http://a"),
+        );
+    }
+}
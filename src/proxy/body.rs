@@ -0,0 +1,296 @@
+use crate::proxy::replacement::Pattern;
+
+/// Media types that receive body replacements when a rule does not set
+/// `replace_content_types`. Multipart bodies are rewritten part by part.
+pub const DEFAULT_REPLACEABLE_TYPES: &[&str] = &[
+    "text/*",
+    "application/json",
+    "application/*+json",
+    "application/xml",
+    "application/*+xml",
+    "application/x-www-form-urlencoded",
+    "application/javascript",
+    "multipart/form-data",
+];
+
+/// Content type assumed for a multipart part without its own header (RFC 7578).
+const DEFAULT_PART_TYPE: &str = "text/plain";
+
+/// A body replacement whose value has already been selected for this request.
+#[derive(Debug, Clone)]
+pub struct ResolvedReplacement<'a> {
+    pub pattern: &'a Pattern,
+    pub value: String,
+    /// Whether `value` was rendered from a template that may refer to regex
+    /// captures, rather than being a source value to insert verbatim.
+    pub expand_captures: bool,
+}
+
+/// Returns whether a body with the given `Content-Type` should be rewritten.
+/// Bodies without a content type are treated as replaceable.
+pub fn is_replaceable(content_type: Option<&str>, allowlist: &[String]) -> bool {
+    match content_type {
+        Some(content_type) => allows(allowlist, &media_type(content_type)),
+        None => true,
+    }
+}
+
+/// Applies `replacements` to `body`, descending into multipart bodies so that
+/// binary parts are forwarded byte-for-byte.
+pub fn rewrite_body(
+    body: &[u8],
+    content_type: Option<&str>,
+    allowlist: &[String],
+    replacements: &[ResolvedReplacement<'_>],
+) -> anyhow::Result<Vec<u8>> {
+    if let Some(content_type) = content_type {
+        if media_type(content_type).starts_with("multipart/") {
+            if let Some(boundary) = boundary(content_type) {
+                return rewrite_multipart(body, &boundary, allowlist, replacements);
+            }
+        }
+    }
+
+    apply_all(body, replacements)
+}
+
+fn apply_all(body: &[u8], replacements: &[ResolvedReplacement<'_>]) -> anyhow::Result<Vec<u8>> {
+    let mut body = body.to_vec();
+    for replacement in replacements {
+        body = match replacement.expand_captures {
+            true => replacement.pattern.apply_expanding(&body, &replacement.value)?,
+            false => replacement.pattern.apply(&body, &replacement.value)?,
+        };
+    }
+    Ok(body)
+}
+
+fn rewrite_multipart(
+    body: &[u8],
+    boundary: &str,
+    allowlist: &[String],
+    replacements: &[ResolvedReplacement<'_>],
+) -> anyhow::Result<Vec<u8>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let positions = delimiter_positions(body, &delimiter);
+    if positions.is_empty() {
+        return Ok(body.to_vec());
+    }
+
+    let mut result = Vec::with_capacity(body.len());
+    // Preamble before the first delimiter is copied verbatim.
+    result.extend_from_slice(&body[..positions[0]]);
+
+    for (index, &start) in positions.iter().enumerate() {
+        let end = positions.get(index + 1).copied().unwrap_or(body.len());
+        let segment = &body[start..end];
+        let after_delimiter = &segment[delimiter.len()..];
+
+        // The closing delimiter and epilogue, or a malformed part: keep as-is.
+        let header_end = match find_all(after_delimiter, b"\r\n\r\n").first() {
+            Some(&pos) if !after_delimiter.starts_with(b"--") => pos + 4,
+            _ => {
+                result.extend_from_slice(segment);
+                continue;
+            }
+        };
+
+        let headers = &after_delimiter[..header_end];
+        let content = &after_delimiter[header_end..];
+        // The CRLF preceding the next delimiter belongs to the delimiter.
+        let (content, trailer): (&[u8], &[u8]) = match content.strip_suffix(b"\r\n") {
+            Some(content) => (content, b"\r\n"),
+            None => (content, b""),
+        };
+
+        result.extend_from_slice(&delimiter);
+        result.extend_from_slice(headers);
+
+        let part_type = part_content_type(headers).unwrap_or_else(|| DEFAULT_PART_TYPE.to_string());
+        if allows(allowlist, &media_type(&part_type)) {
+            result.extend_from_slice(&apply_all(content, replacements)?);
+        } else {
+            result.extend_from_slice(content);
+        }
+        result.extend_from_slice(trailer);
+    }
+
+    Ok(result)
+}
+
+fn allows(allowlist: &[String], media_type: &str) -> bool {
+    if allowlist.is_empty() {
+        DEFAULT_REPLACEABLE_TYPES.iter().any(|pattern| media_type_matches(pattern, media_type))
+    } else {
+        allowlist.iter().any(|pattern| media_type_matches(pattern, media_type))
+    }
+}
+
+/// Matches `type/subtype` against patterns such as `*/*`, `text/*` or `application/*+json`.
+fn media_type_matches(pattern: &str, media_type: &str) -> bool {
+    let pattern = pattern.trim().to_ascii_lowercase();
+    let (pattern_type, pattern_subtype) = pattern.split_once('/').unwrap_or((pattern.as_str(), "*"));
+    let (actual_type, actual_subtype) = media_type.split_once('/').unwrap_or((media_type, ""));
+
+    if pattern_type != "*" && pattern_type != actual_type {
+        return false;
+    }
+
+    match pattern_subtype {
+        "*" => true,
+        subtype if subtype.starts_with("*+") => actual_subtype.ends_with(&subtype[1..]),
+        subtype => subtype == actual_subtype,
+    }
+}
+
+fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+fn boundary(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("boundary") {
+            Some(value.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+fn part_content_type(headers: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(headers).lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("content-type") {
+            Some(value.trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Offsets of the multipart delimiters in `body` (RFC 2046 §5.1.1): the
+/// `--boundary` must start the body or follow a CRLF, and be followed by
+/// optional whitespace and a CRLF, or by `--` for the closing delimiter.
+/// The boundary text elsewhere, e.g. inside a binary part, is not a delimiter.
+fn delimiter_positions(body: &[u8], delimiter: &[u8]) -> Vec<usize> {
+    let mut positions = Vec::new();
+    for pos in find_all(body, delimiter) {
+        if pos != 0 && !body[..pos].ends_with(b"\r\n") {
+            continue;
+        }
+        let rest = &body[pos + delimiter.len()..];
+        if rest.starts_with(b"--") {
+            positions.push(pos);
+            // Whatever follows the closing delimiter is epilogue.
+            break;
+        }
+        let padding = rest.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+        if rest[padding..].starts_with(b"\r\n") {
+            positions.push(pos);
+        }
+    }
+    positions
+}
+
+fn find_all(haystack: &[u8], needle: &[u8]) -> Vec<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return Vec::new();
+    }
+
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(|(_, window)| *window == needle)
+        .map(|(pos, _)| pos)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ReplacementMode;
+
+    fn key_replacement(pattern: &Pattern) -> Vec<ResolvedReplacement<'_>> {
+        vec![ResolvedReplacement {
+            pattern,
+            value: "secret".to_string(),
+            expand_captures: false,
+        }]
+    }
+
+    fn key_pattern() -> Pattern {
+        Pattern::compile("{{KEY}}", ReplacementMode::Literal).unwrap()
+    }
+
+    #[test]
+    fn test_is_replaceable_defaults() {
+        assert!(is_replaceable(None, &[]));
+        assert!(is_replaceable(Some("application/json; charset=utf-8"), &[]));
+        assert!(is_replaceable(Some("application/vnd.api+json"), &[]));
+        assert!(is_replaceable(Some("text/plain"), &[]));
+        assert!(!is_replaceable(Some("application/octet-stream"), &[]));
+        assert!(!is_replaceable(Some("image/png"), &[]));
+    }
+
+    #[test]
+    fn test_is_replaceable_allowlist() {
+        let allowlist = vec!["application/json".to_string()];
+        assert!(is_replaceable(Some("application/json"), &allowlist));
+        assert!(!is_replaceable(Some("text/plain"), &allowlist));
+
+        let allowlist = vec!["*/*".to_string()];
+        assert!(is_replaceable(Some("application/octet-stream"), &allowlist));
+    }
+
+    #[test]
+    fn test_multipart_rewrites_text_parts_only() {
+        let mut body = Vec::new();
+        body.extend_from_slice(b"--XyZ\r\nContent-Disposition: form-data; name=\"key\"\r\n\r\n{{KEY}}\r\n");
+        body.extend_from_slice(b"--XyZ\r\nContent-Disposition: form-data; name=\"file\"\r\nContent-Type: application/octet-stream\r\n\r\n");
+        body.extend_from_slice(&[0xff, 0x00, b'{', b'{', b'K', b'E', b'Y', b'}', b'}']);
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+
+        let result = rewrite_body(&body, Some("multipart/form-data; boundary=XyZ"), &[], &key_replacement(&key_pattern())).unwrap();
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(b"--XyZ\r\nContent-Disposition: form-data; name=\"key\"\r\n\r\nsecret\r\n");
+        expected.extend_from_slice(b"--XyZ\r\nContent-Disposition: form-data; name=\"file\"\r\nContent-Type: application/octet-stream\r\n\r\n");
+        expected.extend_from_slice(&[0xff, 0x00, b'{', b'{', b'K', b'E', b'Y', b'}', b'}']);
+        expected.extend_from_slice(b"\r\n--XyZ--\r\n");
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_multipart_ignores_boundary_inside_parts() {
+        // The boundary bytes appear mid-line in the binary part, and as a
+        // prefix of a longer word in the text part.
+        let mut file = vec![0xff, 0x00];
+        file.extend_from_slice(b"--XyZ\r\nContent-Type: text/plain\r\n\r\n{{KEY}}");
+        let mut body = Vec::new();
+        body.extend_from_slice(b"--XyZ\r\nContent-Type: application/octet-stream\r\n\r\n");
+        body.extend_from_slice(&file);
+        body.extend_from_slice(b"\r\n--XyZ\r\nContent-Type: text/plain\r\n\r\n{{KEY}}\r\n--XyZabc {{KEY}}\r\n");
+        body.extend_from_slice(b"--XyZ--\r\n--XyZ\r\n\r\n{{KEY}}");
+
+        let result = rewrite_body(&body, Some("multipart/form-data; boundary=XyZ"), &[], &key_replacement(&key_pattern())).unwrap();
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(b"--XyZ\r\nContent-Type: application/octet-stream\r\n\r\n");
+        expected.extend_from_slice(&file);
+        expected.extend_from_slice(b"\r\n--XyZ\r\nContent-Type: text/plain\r\n\r\nsecret\r\n--XyZabc secret\r\n");
+        expected.extend_from_slice(b"--XyZ--\r\n--XyZ\r\n\r\n{{KEY}}");
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_rewrite_plain_body() {
+        let result = rewrite_body(b"key={{KEY}}", Some("text/plain"), &[], &key_replacement(&key_pattern())).unwrap();
+        assert_eq!(result, b"key=secret");
+    }
+}
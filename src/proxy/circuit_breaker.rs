@@ -0,0 +1,385 @@
+use crate::config::CircuitBreakerConfig;
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests flow normally.
+    Closed,
+    /// Requests are not sent until `open_duration` has passed.
+    Open,
+    /// A limited number of probe requests decide whether to close again.
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+/// Published whenever a target's circuit changes state.
+#[derive(Debug, Clone)]
+pub struct CircuitTransition {
+    pub rule: String,
+    pub target: String,
+    pub to: CircuitState,
+    pub consecutive_failures: u32,
+}
+
+/// A point-in-time view of one target's circuit.
+#[derive(Debug, Clone)]
+pub struct CircuitSnapshot {
+    pub rule: String,
+    pub target: String,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    /// Share of failures among the recent requests in the window.
+    pub failure_rate: f64,
+    /// Time until an open circuit lets a probe through.
+    pub retry_in: Option<Duration>,
+}
+
+/// Handed out by `CircuitBreakers::try_acquire` and passed back to `record`,
+/// so that outcomes of requests admitted before a state change (e.g. slow
+/// requests from before the circuit opened) are not counted as probes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Admission {
+    generation: u64,
+}
+
+/// Circuit breakers per rule target.
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    breakers: DashMap<(String, String), Breaker>,
+    events: broadcast::Sender<CircuitTransition>,
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: CircuitState,
+    /// When the circuit last changed state.
+    since: Instant,
+    /// When an open circuit lets a probe through.
+    retry_at: Instant,
+    consecutive_failures: u32,
+    /// Recent outcomes, `true` for failures.
+    outcomes: VecDeque<bool>,
+    probes_started: u32,
+    probe_successes: u32,
+    /// Bumped on every state change and probe round; see `Admission`.
+    generation: u64,
+}
+
+impl Breaker {
+    fn new() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            since: Instant::now(),
+            retry_at: Instant::now(),
+            consecutive_failures: 0,
+            outcomes: VecDeque::new(),
+            probes_started: 0,
+            probe_successes: 0,
+            generation: 0,
+        }
+    }
+
+    fn failure_rate(&self) -> f64 {
+        if self.outcomes.is_empty() {
+            return 0.0;
+        }
+        self.outcomes.iter().filter(|failed| **failed).count() as f64 / self.outcomes.len() as f64
+    }
+
+    fn should_open(&self, config: &CircuitBreakerConfig) -> bool {
+        let consecutive = config.consecutive_failures > 0 && self.consecutive_failures >= config.consecutive_failures;
+        let rate = config.failure_rate.is_some_and(|threshold| {
+            self.outcomes.len() >= config.min_requests && self.failure_rate() >= threshold
+        });
+        consecutive || rate
+    }
+
+    /// Whether a request may go through now. Open circuits go half-open once
+    /// `open_duration` has passed. Half-open circuits whose probes never
+    /// reported back (e.g. cancelled requests) allow new probes after another
+    /// `open_duration`.
+    fn allows(&self, config: &CircuitBreakerConfig, now: Instant) -> bool {
+        let open_duration = Duration::from_secs(config.open_duration);
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => now >= self.retry_at,
+            CircuitState::HalfOpen => {
+                self.probes_started < config.half_open_probes || now.duration_since(self.since) >= open_duration
+            }
+        }
+    }
+
+    fn transition(&mut self, to: CircuitState, now: Instant) -> CircuitState {
+        let from = self.state;
+        self.state = to;
+        self.since = now;
+        self.probes_started = 0;
+        self.probe_successes = 0;
+        self.generation += 1;
+        if to == CircuitState::Closed {
+            self.consecutive_failures = 0;
+            self.outcomes.clear();
+        }
+        from
+    }
+}
+
+impl CircuitBreakers {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            breakers: DashMap::new(),
+            events,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<CircuitTransition> {
+        self.events.subscribe()
+    }
+
+    /// Whether `target` currently accepts requests, without taking a probe slot.
+    pub fn is_available(&self, rule: &str, target: &str, config: &CircuitBreakerConfig) -> bool {
+        self.breakers
+            .get(&(rule.to_string(), target.to_string()))
+            .is_none_or(|breaker| breaker.allows(config, Instant::now()))
+    }
+
+    /// Lets a request through to `target` if its circuit allows it, taking a
+    /// probe slot when the circuit is (or goes) half-open. The returned
+    /// admission goes back to `record` with the request's outcome.
+    pub fn try_acquire(&self, rule: &str, target: &str, config: &CircuitBreakerConfig) -> Option<Admission> {
+        let now = Instant::now();
+        let mut breaker = self.breakers
+            .entry((rule.to_string(), target.to_string()))
+            .or_insert_with(Breaker::new);
+
+        if !breaker.allows(config, now) {
+            return None;
+        }
+
+        match breaker.state {
+            CircuitState::Closed => {}
+            CircuitState::Open => {
+                let from = breaker.transition(CircuitState::HalfOpen, now);
+                self.publish(rule, target, from, &breaker);
+                breaker.probes_started = 1;
+            }
+            CircuitState::HalfOpen => {
+                if breaker.probes_started >= config.half_open_probes {
+                    // Probes went missing; start a new round.
+                    breaker.since = now;
+                    breaker.probes_started = 0;
+                    breaker.probe_successes = 0;
+                    breaker.generation += 1;
+                }
+                breaker.probes_started += 1;
+            }
+        }
+        Some(Admission { generation: breaker.generation })
+    }
+
+    /// Records the outcome of a request to `target`. Outcomes of requests
+    /// admitted before the circuit last changed state are ignored.
+    pub fn record(&self, rule: &str, target: &str, config: &CircuitBreakerConfig, admission: Admission, failed: bool) {
+        let now = Instant::now();
+        let mut breaker = self.breakers
+            .entry((rule.to_string(), target.to_string()))
+            .or_insert_with(Breaker::new);
+        if admission.generation != breaker.generation {
+            return;
+        }
+
+        breaker.consecutive_failures = if failed { breaker.consecutive_failures + 1 } else { 0 };
+        breaker.outcomes.push_back(failed);
+        while breaker.outcomes.len() > config.window {
+            breaker.outcomes.pop_front();
+        }
+
+        let next = match breaker.state {
+            CircuitState::Closed if failed && breaker.should_open(config) => Some(CircuitState::Open),
+            CircuitState::HalfOpen if failed => Some(CircuitState::Open),
+            CircuitState::HalfOpen => {
+                breaker.probe_successes += 1;
+                (breaker.probe_successes >= config.half_open_probes).then_some(CircuitState::Closed)
+            }
+            _ => None,
+        };
+
+        if let Some(next) = next {
+            let from = breaker.transition(next, now);
+            if next == CircuitState::Open {
+                breaker.retry_at = now + Duration::from_secs(config.open_duration);
+            }
+            self.publish(rule, target, from, &breaker);
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<CircuitSnapshot> {
+        let now = Instant::now();
+        let mut snapshot: Vec<CircuitSnapshot> = self.breakers
+            .iter()
+            .map(|entry| {
+                let ((rule, target), breaker) = entry.pair();
+                CircuitSnapshot {
+                    rule: rule.clone(),
+                    target: target.clone(),
+                    state: breaker.state,
+                    consecutive_failures: breaker.consecutive_failures,
+                    failure_rate: breaker.failure_rate(),
+                    retry_in: (breaker.state == CircuitState::Open)
+                        .then(|| breaker.retry_at.saturating_duration_since(now)),
+                }
+            })
+            .collect();
+
+        snapshot.sort_by(|a, b| (&a.rule, &a.target).cmp(&(&b.rule, &b.target)));
+        snapshot
+    }
+
+    fn publish(&self, rule: &str, target: &str, from: CircuitState, breaker: &Breaker) {
+        if breaker.state == CircuitState::Open {
+            warn!(
+                "Circuit for target {} of rule {} opened ({} consecutive failures, {:.0}% recent failures)",
+                target, rule, breaker.consecutive_failures, breaker.failure_rate() * 100.0
+            );
+        } else {
+            info!("Circuit for target {} of rule {}: {} -> {}", target, rule, from.as_str(), breaker.state.as_str());
+        }
+
+        // Sending only fails when nobody is subscribed.
+        let _ = self.events.send(CircuitTransition {
+            rule: rule.to_string(),
+            target: target.to_string(),
+            to: breaker.state,
+            consecutive_failures: breaker.consecutive_failures,
+        });
+    }
+}
+
+impl Default for CircuitBreakers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            consecutive_failures: 3,
+            failure_rate: None,
+            window: 10,
+            min_requests: 4,
+            open_duration: 0,
+            half_open_probes: 2,
+            failure_statuses: vec![502],
+        }
+    }
+
+    /// Sends one request through the breaker, if it is let through.
+    fn request(breakers: &CircuitBreakers, config: &CircuitBreakerConfig, failed: bool) {
+        if let Some(admission) = breakers.try_acquire("r", "http://a", config) {
+            breakers.record("r", "http://a", config, admission, failed);
+        }
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breakers = CircuitBreakers::new();
+        let mut events = breakers.subscribe();
+        let config = CircuitBreakerConfig { open_duration: 60, ..config() };
+
+        for _ in 0..3 {
+            let admission = breakers.try_acquire("r", "http://a", &config).unwrap();
+            breakers.record("r", "http://a", &config, admission, true);
+        }
+        assert!(!breakers.is_available("r", "http://a", &config));
+        assert!(breakers.try_acquire("r", "http://a", &config).is_none());
+        assert!(breakers.is_available("r", "http://b", &config));
+
+        let event = events.try_recv().unwrap();
+        assert_eq!((event.to, event.consecutive_failures), (CircuitState::Open, 3));
+        let snapshot = breakers.snapshot();
+        assert!(snapshot[0].retry_in.unwrap() > Duration::from_secs(50));
+    }
+
+    #[test]
+    fn test_successful_probes_close() {
+        let breakers = CircuitBreakers::new();
+        let config = config();
+        for _ in 0..3 {
+            request(&breakers, &config, true);
+        }
+        let mut events = breakers.subscribe();
+
+        // open_duration is 0, so the circuit lets probes through at once.
+        let first = breakers.try_acquire("r", "http://a", &config).unwrap();
+        let second = breakers.try_acquire("r", "http://a", &config).unwrap();
+        breakers.record("r", "http://a", &config, first, false);
+        breakers.record("r", "http://a", &config, second, false);
+
+        let states: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).map(|event| event.to).collect();
+        assert_eq!(states, vec![CircuitState::HalfOpen, CircuitState::Closed]);
+    }
+
+    #[test]
+    fn test_failed_probe_reopens() {
+        let breakers = CircuitBreakers::new();
+        let config = config();
+
+        for _ in 0..3 {
+            request(&breakers, &config, true);
+        }
+        request(&breakers, &config, true);
+        assert_eq!(breakers.snapshot()[0].state, CircuitState::Open);
+    }
+
+    #[test]
+    fn test_opens_on_failure_rate() {
+        let breakers = CircuitBreakers::new();
+        let config = CircuitBreakerConfig { consecutive_failures: 0, failure_rate: Some(0.5), ..config() };
+
+        for failed in [true, false, true] {
+            request(&breakers, &config, failed);
+        }
+        assert_eq!(breakers.snapshot()[0].state, CircuitState::Closed);
+
+        request(&breakers, &config, true);
+        let snapshot = breakers.snapshot();
+        assert_eq!(snapshot[0].state, CircuitState::Open);
+        assert_eq!(snapshot[0].failure_rate, 0.75);
+    }
+
+    #[test]
+    fn test_outcomes_from_before_a_transition_are_ignored() {
+        let breakers = CircuitBreakers::new();
+        let config = CircuitBreakerConfig { consecutive_failures: 1, half_open_probes: 1, ..config() };
+
+        let slow = breakers.try_acquire("r", "http://a", &config).unwrap();
+        request(&breakers, &config, true);
+        assert_eq!(breakers.snapshot()[0].state, CircuitState::Open);
+
+        // The slow request was admitted while closed, so its success is not a probe.
+        let probe = breakers.try_acquire("r", "http://a", &config).unwrap();
+        breakers.record("r", "http://a", &config, slow, false);
+        assert_eq!(breakers.snapshot()[0].state, CircuitState::HalfOpen);
+
+        breakers.record("r", "http://a", &config, probe, true);
+        assert_eq!(breakers.snapshot()[0].state, CircuitState::Open);
+    }
+}
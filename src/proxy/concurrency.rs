@@ -0,0 +1,234 @@
+use crate::config::ConcurrencyConfig;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// In-flight request counts and limits, per rule and per rule target.
+#[derive(Debug, Default)]
+pub struct ConcurrencyLimiter {
+    rules: DashMap<String, Arc<Limit>>,
+    targets: DashMap<(String, String), Arc<Limit>>,
+}
+
+#[derive(Debug)]
+struct Limit {
+    max: Option<usize>,
+    semaphore: Option<Arc<Semaphore>>,
+    in_flight: Arc<AtomicUsize>,
+    waiting: AtomicUsize,
+}
+
+impl Limit {
+    fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            semaphore: max.map(|max| Arc::new(Semaphore::new(max))),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Returned when a request cannot get an in-flight slot.
+#[derive(Debug)]
+pub struct Overloaded {
+    pub scope: String,
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is overloaded: {}", self.scope, self.reason)
+    }
+}
+
+impl std::error::Error for Overloaded {}
+
+/// An in-flight slot, released when dropped.
+#[derive(Debug)]
+pub struct InFlight {
+    in_flight: Arc<AtomicUsize>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A place in a wait queue, given up when dropped so that requests abandoned
+/// while queued do not keep counting against `max_queue`.
+struct Queued<'a> {
+    waiting: &'a AtomicUsize,
+    position: usize,
+}
+
+impl<'a> Queued<'a> {
+    fn enter(waiting: &'a AtomicUsize) -> Self {
+        let position = waiting.fetch_add(1, Ordering::Relaxed);
+        Self { waiting, position }
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ConcurrencyLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes an in-flight slot for `rule`, waiting in its queue if the rule is at its limit.
+    pub async fn acquire_rule(&self, rule: &str, config: Option<&ConcurrencyConfig>) -> Result<InFlight, Overloaded> {
+        let max = config.and_then(|config| config.max_in_flight);
+        let limit = limit_for(&self.rules, rule.to_string(), max);
+        acquire(&limit, config, || format!("rule '{}'", rule)).await
+    }
+
+    /// Takes an in-flight slot for `target` of `rule`, waiting in its queue if
+    /// the target is at its limit.
+    pub async fn acquire_target(&self, rule: &str, target: &str, config: Option<&ConcurrencyConfig>) -> Result<InFlight, Overloaded> {
+        let max = config.and_then(|config| config.max_in_flight_per_target);
+        let limit = limit_for(&self.targets, (rule.to_string(), target.to_string()), max);
+        acquire(&limit, config, || format!("target '{}' of rule '{}'", target, rule)).await
+    }
+
+    /// Requests currently forwarded to `target` by `rule`.
+    pub fn in_flight(&self, rule: &str, target: &str) -> usize {
+        self.targets
+            .get(&(rule.to_string(), target.to_string()))
+            .map_or(0, |limit| limit.in_flight.load(Ordering::Relaxed))
+    }
+}
+
+/// Returns the limit for `key`, replacing it if the configured maximum changed.
+fn limit_for<K>(limits: &DashMap<K, Arc<Limit>>, key: K, max: Option<usize>) -> Arc<Limit>
+where
+    K: std::hash::Hash + Eq,
+{
+    let mut limit = limits.entry(key).or_insert_with(|| Arc::new(Limit::new(max)));
+    if limit.max != max {
+        *limit = Arc::new(Limit::new(max));
+    }
+    limit.clone()
+}
+
+async fn acquire(
+    limit: &Limit,
+    config: Option<&ConcurrencyConfig>,
+    scope: impl Fn() -> String,
+) -> Result<InFlight, Overloaded> {
+    let permit = match (&limit.semaphore, config) {
+        (Some(semaphore), Some(config)) => Some(match semaphore.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let queued = Queued::enter(&limit.waiting);
+                if queued.position >= config.max_queue {
+                    return Err(Overloaded { scope: scope(), reason: "queue is full" });
+                }
+
+                let timeout = Duration::from_millis(config.queue_timeout_ms);
+                let permit = tokio::time::timeout(timeout, semaphore.clone().acquire_owned()).await;
+                drop(queued);
+                match permit {
+                    Ok(Ok(permit)) => permit,
+                    _ => return Err(Overloaded { scope: scope(), reason: "timed out in queue" }),
+                }
+            }
+        }),
+        _ => None,
+    };
+
+    limit.in_flight.fetch_add(1, Ordering::Relaxed);
+    Ok(InFlight {
+        in_flight: limit.in_flight.clone(),
+        _permit: permit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_in_flight: Option<usize>, max_queue: usize) -> ConcurrencyConfig {
+        ConcurrencyConfig {
+            max_in_flight,
+            max_in_flight_per_target: Some(1),
+            max_queue,
+            queue_timeout_ms: 20,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_full_and_timeout() {
+        let limiter = ConcurrencyLimiter::new();
+        let config = config(Some(1), 0);
+
+        let held = limiter.acquire_rule("r", Some(&config)).await.unwrap();
+        let error = limiter.acquire_rule("r", Some(&config)).await.unwrap_err();
+        assert_eq!(error.reason, "queue is full");
+
+        let queued = ConcurrencyConfig { max_queue: 1, ..config.clone() };
+        let error = limiter.acquire_rule("r", Some(&queued)).await.unwrap_err();
+        assert_eq!(error.reason, "timed out in queue");
+
+        drop(held);
+        assert!(limiter.acquire_rule("r", Some(&config)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queued_request_gets_released_slot() {
+        let limiter = Arc::new(ConcurrencyLimiter::new());
+        let mut config = config(None, 1);
+        config.queue_timeout_ms = 1000;
+
+        let held = limiter.acquire_target("r", "http://a", Some(&config)).await.unwrap();
+        assert_eq!(limiter.in_flight("r", "http://a"), 1);
+
+        let waiter = {
+            let limiter = limiter.clone();
+            let config = config.clone();
+            tokio::spawn(async move { limiter.acquire_target("r", "http://a", Some(&config)).await.is_ok() })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(held);
+        assert!(waiter.await.unwrap());
+        assert_eq!(limiter.in_flight("r", "http://a"), 0);
+
+        // Without a limit, requests are only counted.
+        let _slots = [
+            limiter.acquire_target("r", "http://b", None).await.unwrap(),
+            limiter.acquire_target("r", "http://b", None).await.unwrap(),
+        ];
+        assert_eq!(limiter.in_flight("r", "http://b"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_waiter_leaves_queue() {
+        let limiter = Arc::new(ConcurrencyLimiter::new());
+        let mut config = config(Some(1), 1);
+        config.queue_timeout_ms = 10_000;
+
+        let held = limiter.acquire_rule("r", Some(&config)).await.unwrap();
+        let waiter = {
+            let limiter = limiter.clone();
+            let config = config.clone();
+            tokio::spawn(async move { limiter.acquire_rule("r", Some(&config)).await.is_ok() })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limiter.rules.get("r").unwrap().waiting.load(Ordering::Relaxed), 1);
+
+        waiter.abort();
+        let _ = waiter.await;
+        assert_eq!(limiter.rules.get("r").unwrap().waiting.load(Ordering::Relaxed), 0);
+
+        drop(held);
+        assert!(limiter.acquire_rule("r", Some(&config)).await.is_ok());
+    }
+}
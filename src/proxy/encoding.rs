@@ -0,0 +1,193 @@
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// A `Content-Encoding` coding that the proxy can decode and re-encode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl ContentCoding {
+    fn from_token(token: &str) -> Option<Option<Self>> {
+        match token.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Some(None),
+            "gzip" | "x-gzip" => Some(Some(Self::Gzip)),
+            "deflate" => Some(Some(Self::Deflate)),
+            "br" => Some(Some(Self::Brotli)),
+            "zstd" => Some(Some(Self::Zstd)),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a `Content-Encoding` header into the codings in the order they were
+/// applied. Returns `None` if any coding is unsupported.
+pub fn parse_content_encoding(header: &str) -> Option<Vec<ContentCoding>> {
+    let mut codings = Vec::new();
+    for token in header.split(',') {
+        if let Some(coding) = ContentCoding::from_token(token)? {
+            codings.push(coding);
+        }
+    }
+    Some(codings)
+}
+
+/// Returned when a body, or what it decodes to, is larger than allowed.
+#[derive(Debug)]
+pub struct BodyTooLarge {
+    pub limit: usize,
+}
+
+impl std::fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "body exceeds the {} byte limit", self.limit)
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+/// Removes the codings from `body`, undoing them in reverse order. Fails
+/// with `BodyTooLarge` as soon as any stage produces more than `limit` bytes,
+/// so a small compressed body cannot expand without bound.
+pub fn decode(body: &[u8], codings: &[ContentCoding], limit: usize) -> anyhow::Result<Vec<u8>> {
+    if body.len() > limit {
+        return Err(BodyTooLarge { limit }.into());
+    }
+
+    let mut data = body.to_vec();
+    for coding in codings.iter().rev() {
+        data = decode_one(&data, *coding, limit)?;
+    }
+    Ok(data)
+}
+
+/// Applies the codings to `body` in order.
+pub fn encode(body: &[u8], codings: &[ContentCoding]) -> anyhow::Result<Vec<u8>> {
+    let mut data = body.to_vec();
+    for coding in codings {
+        data = encode_one(&data, *coding)?;
+    }
+    Ok(data)
+}
+
+fn decode_one(body: &[u8], coding: ContentCoding, limit: usize) -> anyhow::Result<Vec<u8>> {
+    match coding {
+        ContentCoding::Gzip => read_limited(GzDecoder::new(body), limit),
+        // "deflate" is zlib-wrapped per RFC 9110, but raw deflate is common in the wild.
+        ContentCoding::Deflate => match read_limited(ZlibDecoder::new(body), limit) {
+            Err(e) if !e.is::<BodyTooLarge>() => read_limited(DeflateDecoder::new(body), limit),
+            result => result,
+        },
+        ContentCoding::Brotli => read_limited(brotli::Decompressor::new(body, 4096), limit),
+        ContentCoding::Zstd => read_limited(zstd::stream::read::Decoder::new(body)?, limit),
+    }
+}
+
+/// Reads `reader` to the end, reading one byte past `limit` to detect overflow.
+fn read_limited(reader: impl Read, limit: usize) -> anyhow::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    reader.take(limit as u64 + 1).read_to_end(&mut decoded)?;
+    if decoded.len() > limit {
+        return Err(BodyTooLarge { limit }.into());
+    }
+    Ok(decoded)
+}
+
+fn encode_one(body: &[u8], coding: ContentCoding) -> anyhow::Result<Vec<u8>> {
+    match coding {
+        ContentCoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            Ok(encoder.finish()?)
+        }
+        ContentCoding::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            Ok(encoder.finish()?)
+        }
+        ContentCoding::Brotli => {
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut encoded, 4096, 5, 22);
+                encoder.write_all(body)?;
+            }
+            Ok(encoded)
+        }
+        ContentCoding::Zstd => Ok(zstd::stream::encode_all(body, 0)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &[u8] = b"{\"auth\":{\"api_key\":\"{{API_KEY}}\"}}";
+    const LIMIT: usize = 1024;
+
+    fn assert_round_trip(coding: ContentCoding) {
+        let encoded = encode(SAMPLE, &[coding]).unwrap();
+        assert_ne!(encoded, SAMPLE);
+        assert_eq!(decode(&encoded, &[coding], LIMIT).unwrap(), SAMPLE);
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        assert_round_trip(ContentCoding::Gzip);
+    }
+
+    #[test]
+    fn test_deflate_round_trip() {
+        assert_round_trip(ContentCoding::Deflate);
+    }
+
+    #[test]
+    fn test_raw_deflate_is_accepted() {
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(SAMPLE).unwrap();
+        let encoded = encoder.finish().unwrap();
+        assert_eq!(decode(&encoded, &[ContentCoding::Deflate], LIMIT).unwrap(), SAMPLE);
+    }
+
+    #[test]
+    fn test_brotli_round_trip() {
+        assert_round_trip(ContentCoding::Brotli);
+    }
+
+    #[test]
+    fn test_zstd_round_trip() {
+        assert_round_trip(ContentCoding::Zstd);
+    }
+
+    #[test]
+    fn test_stacked_codings() {
+        let codings = parse_content_encoding("gzip, br").unwrap();
+        assert_eq!(codings, vec![ContentCoding::Gzip, ContentCoding::Brotli]);
+
+        let encoded = encode(SAMPLE, &codings).unwrap();
+        assert_eq!(decode(&encoded, &codings, LIMIT).unwrap(), SAMPLE);
+    }
+
+    #[test]
+    fn test_decode_limit() {
+        let bomb = vec![0u8; LIMIT * 64];
+        for coding in [ContentCoding::Gzip, ContentCoding::Deflate, ContentCoding::Brotli, ContentCoding::Zstd] {
+            let encoded = encode(&bomb, &[coding]).unwrap();
+            assert!(encoded.len() < LIMIT);
+            assert!(decode(&encoded, &[coding], LIMIT).unwrap_err().is::<BodyTooLarge>());
+            assert_eq!(decode(&encoded, &[coding], bomb.len()).unwrap(), bomb);
+        }
+        assert!(decode(&bomb, &[], LIMIT).unwrap_err().is::<BodyTooLarge>());
+    }
+
+    #[test]
+    fn test_parse_content_encoding() {
+        assert_eq!(parse_content_encoding("identity").unwrap(), vec![]);
+        assert_eq!(parse_content_encoding("x-gzip").unwrap(), vec![ContentCoding::Gzip]);
+        assert!(parse_content_encoding("compress").is_none());
+    }
+}
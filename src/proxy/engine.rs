@@ -1,46 +1,88 @@
-use crate::config::{ForwardingRule, ContentSource};
-use crate::content::ContentManager;
-use crate::proxy::{ProxyRouter, RoundRobinManager};
+use crate::config::{ForwardingRule, ContentCacheConfig, LoadBalancingStrategy, ContentErrorPolicy, ContentSource, HeaderOperationKind, QuotaConfig, ReplacementMode, ResponseCacheConfig};
+use crate::content::cache::{CacheEntryInfo, CacheStatsSnapshot};
+use crate::content::secret::fingerprint;
+use crate::content::{ContentManager, ContentSet, ContentValue};
+use crate::proxy::body::{self, ResolvedReplacement};
+use crate::proxy::balancer::LoadBalancer;
+use crate::proxy::circuit_breaker::{CircuitBreakers, CircuitSnapshot, CircuitTransition};
+use crate::proxy::concurrency::ConcurrencyLimiter;
+use crate::proxy::hashing;
+use crate::proxy::quota::{ContentExhausted, LiveScope, UsageSnapshot};
+use crate::proxy::rate_limit::{self, BucketSnapshot, RateLimitDecision, RateLimiter};
+use crate::proxy::response_cache::{self, CacheLookup, CachedResponse, ResponseCache};
+use crate::proxy::encoding::{self, BodyTooLarge};
+use crate::proxy::headers;
+use crate::proxy::template::RequestContext;
+use crate::proxy::upstream::{TimeoutPhase, UpstreamClients, UpstreamTimeout};
+use crate::proxy::router::CompiledRule;
+use crate::proxy::{ProxyRouter, RoundRobinManager, RouteMatch};
 use axum::extract::Request;
-use axum::http::{HeaderName, HeaderValue, Method};
+use axum::http::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, IF_MODIFIED_SINCE, IF_NONE_MATCH, RETRY_AFTER, SET_COOKIE};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method};
 use axum::response::Response;
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
+
+/// A quota-tracked value injected into a request, kept so the upstream
+/// status can be attributed to it.
+struct UsedValue {
+    key: String,
+    value: ContentValue,
+    quota: QuotaConfig,
+}
 
 #[derive(Debug, Clone)]
 pub struct ProxyEngine {
     router: Arc<RwLock<ProxyRouter>>,
     round_robin: Arc<RoundRobinManager>,
     content_manager: Arc<ContentManager>,
-    client: reqwest::Client,
+    response_cache: Arc<ResponseCache>,
+    rate_limiter: Arc<RateLimiter>,
+    concurrency: Arc<ConcurrencyLimiter>,
+    balancer: Arc<LoadBalancer>,
+    breakers: Arc<CircuitBreakers>,
+    clients: Arc<UpstreamClients>,
 }
 
 impl ProxyEngine {
     pub fn new() -> Self {
+        Self::with_content_cache(&ContentCacheConfig::default())
+    }
+
+    pub fn with_content_cache(cache_config: &ContentCacheConfig) -> Self {
         Self {
             router: Arc::new(RwLock::new(ProxyRouter::new())),
             round_robin: Arc::new(RoundRobinManager::new()),
-            content_manager: Arc::new(ContentManager::new()),
-            client: reqwest::Client::new(),
+            content_manager: Arc::new(ContentManager::with_cache_limits(cache_config)),
+            response_cache: Arc::new(ResponseCache::new()),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            concurrency: Arc::new(ConcurrencyLimiter::new()),
+            balancer: Arc::new(LoadBalancer::new()),
+            breakers: Arc::new(CircuitBreakers::new()),
+            clients: Arc::new(UpstreamClients::new()),
         }
     }
 
     pub async fn update_rules(&self, rules: Vec<ForwardingRule>) -> anyhow::Result<()> {
+        self.clients.update(&rules)?;
         let mut router = self.router.write().await;
         router.update_rules(rules)?;
         self.round_robin.clear_content_selectors();
+        self.balancer.clear();
         Ok(())
     }
 
     pub async fn handle_request(&self, mut request: Request) -> Result<Response, axum::http::StatusCode> {
         let path = request.uri().path();
         
-        let rule = {
+        let RouteMatch { rule, captures, compiled } = {
             let router = self.router.read().await;
-            match router.find_matching_rule(path) {
-                Some(rule) => rule.clone(),
+            match router.find_route(path) {
+                Some(route) => route,
                 None => {
                     warn!("No matching rule found for path: {}", path);
                     return Err(axum::http::StatusCode::NOT_FOUND);
@@ -50,21 +92,138 @@ impl ProxyEngine {
 
         info!("Processing request for path: {} using rule: {}", path, rule.name);
 
-        if let Err(e) = self.apply_replacements(&mut request, &rule).await {
-            error!("Failed to apply replacements: {}", e);
-            return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let ctx = RequestContext::from_request(&request, captures);
+
+        let rate_limit = match &rule.rate_limit {
+            Some(limit_config) => {
+                let key = rate_limit::client_key(limit_config, request.headers(), ctx.client_ip);
+                let decision = self.rate_limiter.check(&rule.name, limit_config, &key);
+                if !decision.allowed {
+                    warn!("Rate limit exceeded for rule {}", rule.name);
+                    return Ok(decision.rejection());
+                }
+                Some(decision)
+            }
+            None => None,
+        };
+
+        // Cache lookups see the client's headers, before any replacement.
+        let cache_key = rule.cache.as_ref().and_then(|_| response_cache::request_key(&request));
+        let client_headers = request.headers().clone();
+        let mut stale = None;
+        if let (Some(cache_config), Some(key)) = (&rule.cache, &cache_key) {
+            match self.response_cache.lookup(&rule.name, cache_config, key, &client_headers).await {
+                CacheLookup::Fresh(cached) if !response_cache::request_requires_revalidation(&client_headers) => {
+                    debug!("Serving {} from the response cache of rule {}", key, rule.name);
+                    return self.respond(cached.to_response("HIT"), &rule, &compiled, &ctx, rate_limit.as_ref()).await;
+                }
+                CacheLookup::Fresh(cached) | CacheLookup::Stale(cached) => stale = Some(cached),
+                CacheLookup::Miss => {}
+            }
         }
 
-        let target_url = match self.round_robin.select_target_url(&rule.target_urls) {
+        // Held until the response is built.
+        let _rule_slot = match self.concurrency.acquire_rule(&rule.name, rule.concurrency.as_ref()).await {
+            Ok(slot) => slot,
+            Err(e) => {
+                warn!("Shedding request: {}", e);
+                return Err(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+            }
+        };
+
+        let used_values = match self.apply_replacements(&mut request, &rule, &compiled, &ctx).await {
+            Ok(used_values) => used_values,
+            Err(e) if e.is::<ContentExhausted>() => {
+                warn!("Rejecting request for rule {}: {}", rule.name, e);
+                return Err(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+            }
+            Err(e) if e.is::<BodyTooLarge>() => {
+                warn!("Rejecting request for rule {}: {}", rule.name, e);
+                return Err(axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            Err(e) => {
+                error!("Failed to apply replacements: {}", e);
+                return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+
+        let available = |target: &str| {
+            rule.circuit_breaker.as_ref()
+                .is_none_or(|config| self.breakers.is_available(&rule.name, target, config))
+        };
+        let target_url = match self.select_target(&rule, &ctx, available) {
             Some(url) => url,
             None => {
                 error!("No target URLs available for rule: {}", rule.name);
                 return Err(axum::http::StatusCode::SERVICE_UNAVAILABLE);
             }
         };
+        let admission = match &rule.circuit_breaker {
+            // Another request may have taken the last half-open probe slot.
+            Some(config) => match self.breakers.try_acquire(&rule.name, &target_url, config) {
+                Some(admission) => Some(admission),
+                None => {
+                    warn!("Circuit for target {} of rule {} is open", target_url, rule.name);
+                    return Err(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+                }
+            },
+            None => None,
+        };
+
+        let _target_slot = match self.concurrency.acquire_target(&rule.name, &target_url, rule.concurrency.as_ref()).await {
+            Ok(slot) => slot,
+            Err(e) => {
+                warn!("Shedding request: {}", e);
+                return Err(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+            }
+        };
+
+        // Revalidate a stale entry, unless the client's own conditional
+        // request should decide what it gets back.
+        let stale = stale.filter(|_| !response_cache::is_conditional(&client_headers));
+        if let Some(cached) = &stale {
+            let validators = [(IF_NONE_MATCH, cached.etag()), (IF_MODIFIED_SINCE, cached.last_modified())];
+            for (name, value) in validators {
+                if let Some(value) = value.and_then(|v| HeaderValue::from_str(v).ok()) {
+                    request.headers_mut().insert(name, value);
+                }
+            }
+        }
 
-        match self.forward_request(request, &target_url).await {
-            Ok(response) => Ok(response),
+        let started = Instant::now();
+        let result = self.forward_request(request, &rule, &target_url).await;
+        if let (Some(config), Some(admission)) = (&rule.circuit_breaker, admission) {
+            let failed = result.as_ref().map_or(true, |response| {
+                config.failure_statuses.contains(&response.status().as_u16())
+            });
+            self.breakers.record(&rule.name, &target_url, config, admission, failed);
+        }
+
+        if result.is_err() {
+            self.balancer.record_failure(&rule.name, &target_url, started.elapsed());
+        }
+
+        match result {
+            Ok(response) => {
+                self.balancer.record_latency(&rule.name, &target_url, started.elapsed());
+                self.record_upstream_status(&response, &used_values);
+
+                let response = match (&rule.cache, cache_key) {
+                    (Some(cache_config), Some(key)) => {
+                        self.cache_response(&rule.name, cache_config, key, &client_headers, stale, response).await
+                    }
+                    _ => Ok(response),
+                };
+                let mut response = self.respond(response, &rule, &compiled, &ctx, rate_limit.as_ref()).await?;
+                if let Some(cookie) = self.affinity_cookie(&rule, &ctx, &target_url) {
+                    response.headers_mut().append(SET_COOKIE, cookie);
+                }
+                Ok(response)
+            }
+            Err(e) if e.is::<UpstreamTimeout>() => {
+                warn!("Request to {} timed out: {}", target_url, e);
+                Err(axum::http::StatusCode::GATEWAY_TIMEOUT)
+            }
             Err(e) => {
                 error!("Failed to forward request to {}: {}", target_url, e);
                 Err(axum::http::StatusCode::BAD_GATEWAY)
@@ -72,29 +231,263 @@ impl ProxyEngine {
         }
     }
 
-    async fn apply_replacements(&self, request: &mut Request, rule: &ForwardingRule) -> anyhow::Result<()> {
-        self.apply_header_replacements(request, rule).await?;
-        self.apply_body_replacements(request, rule).await?;
+    /// Picks a target among those `available` (with a closed or probing circuit).
+    fn select_target(&self, rule: &ForwardingRule, ctx: &RequestContext, available: impl Fn(&str) -> bool) -> Option<String> {
+        if let Some(sticky) = &rule.sticky {
+            let pinned = hashing::cookie(&ctx.headers, &sticky.cookie)
+                .and_then(|id| rule.target_urls.iter().find(|target| hashing::target_id(target) == id))
+                .filter(|target| available(target));
+            if let Some(target) = pinned {
+                return Some(target.clone());
+            }
+        }
+
+        if rule.load_balancing == LoadBalancingStrategy::ConsistentHash {
+            // Hash on all targets so keys stay put, rehashing past unavailable ones.
+            let config = rule.consistent_hash.clone().unwrap_or_default();
+            let key = hashing::request_key(&config.key, ctx);
+            return (0..rule.target_urls.len() * 2)
+                .map(|attempt| if attempt == 0 { key.clone() } else { format!("{}#{}", key, attempt) })
+                .filter_map(|key| self.balancer.select_hashed(&rule.name, &rule.target_urls, &config, &key))
+                .find(|target| available(target));
+        }
+
+        let targets: Vec<String> = rule.target_urls.iter().filter(|target| available(target)).cloned().collect();
+        match rule.load_balancing {
+            LoadBalancingStrategy::RoundRobin | LoadBalancingStrategy::WeightedRoundRobin => {
+                self.round_robin.select_target_url(&targets)
+            }
+            strategy => self.balancer.select(&rule.name, strategy, &targets, |target| {
+                self.concurrency.in_flight(&rule.name, target)
+            }),
+        }
+    }
+
+    /// The `Set-Cookie` value pinning the client to `target`, unless its
+    /// affinity cookie already does.
+    fn affinity_cookie(&self, rule: &ForwardingRule, ctx: &RequestContext, target: &str) -> Option<HeaderValue> {
+        let sticky = rule.sticky.as_ref()?;
+        let id = hashing::target_id(target);
+        if hashing::cookie(&ctx.headers, &sticky.cookie).as_deref() == Some(id.as_str()) {
+            return None;
+        }
+
+        let mut cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Lax", sticky.cookie, id);
+        if let Some(max_age) = sticky.max_age {
+            cookie.push_str(&format!("; Max-Age={}", max_age));
+        }
+        HeaderValue::from_str(&cookie).ok()
+    }
+
+    /// Applies the rule's response body replacements and header operations
+    /// and adds the `RateLimit-*` headers.
+    async fn respond(
+        &self,
+        response: anyhow::Result<Response>,
+        rule: &ForwardingRule,
+        compiled: &CompiledRule,
+        ctx: &RequestContext,
+        rate_limit: Option<&RateLimitDecision>,
+    ) -> Result<Response, axum::http::StatusCode> {
+        let response = response.map_err(|e| {
+            error!("Failed to build response: {}", e);
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        let (mut parts, mut body) = response.into_parts();
+        match self.apply_body_replacements(&mut parts.headers, &mut body, rule, compiled, "response", ctx).await {
+            Ok(_) => {}
+            Err(e) if e.is::<BodyTooLarge>() => {
+                warn!("Rejecting upstream response for rule {}: {}", rule.name, e);
+                return Err(axum::http::StatusCode::BAD_GATEWAY);
+            }
+            Err(e) => {
+                error!("Failed to apply response body replacements: {}", e);
+                return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+        let mut response = Response::from_parts(parts, body);
+
+        if let Err(e) = self.apply_header_operations(response.headers_mut(), rule, compiled, "response", ctx, &mut Vec::new()).await {
+            error!("Failed to apply response header operations: {}", e);
+            return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        if let Some(decision) = rate_limit {
+            decision.apply_headers(response.headers_mut());
+        }
+        Ok(response)
+    }
+
+    /// Stores a cacheable upstream response, or refreshes the stale entry a
+    /// `304 Not Modified` confirmed, and marks the response with `X-Cache`.
+    async fn cache_response(
+        &self,
+        rule_name: &str,
+        cache_config: &ResponseCacheConfig,
+        key: String,
+        client_headers: &HeaderMap,
+        stale: Option<CachedResponse>,
+        response: Response,
+    ) -> anyhow::Result<Response> {
+        if let (Some(mut cached), axum::http::StatusCode::NOT_MODIFIED) = (stale, response.status()) {
+            cached.refresh(response.headers(), cache_config);
+            let response = cached.to_response("REVALIDATED");
+            self.response_cache.store(rule_name, cache_config, cached).await;
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await?;
+        if let Some(entry) = response_cache::build_entry(&key, client_headers, parts.status.as_u16(), &parts.headers, &body, cache_config) {
+            self.response_cache.store(rule_name, cache_config, entry).await;
+        }
+        parts.headers.insert(response_cache::X_CACHE, HeaderValue::from_static("MISS"));
+        Ok(Response::from_parts(parts, axum::body::Body::from(body)))
+    }
+
+    async fn apply_replacements(
+        &self,
+        request: &mut Request,
+        rule: &ForwardingRule,
+        compiled: &CompiledRule,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Vec<UsedValue>> {
+        let mut used = Vec::new();
+        self.apply_header_replacements(request, rule, compiled, ctx, &mut used).await?;
+        self.apply_header_operations(request.headers_mut(), rule, compiled, "request", ctx, &mut used).await?;
+        let mut body = std::mem::replace(request.body_mut(), axum::body::Body::empty());
+        used.extend(self.apply_body_replacements(request.headers_mut(), &mut body, rule, compiled, "request", ctx).await?);
+        *request.body_mut() = body;
+        Ok(used)
+    }
+
+    /// Marks quota-tracked values as exhausted when the upstream rejects them.
+    fn record_upstream_status(&self, response: &Response, used_values: &[UsedValue]) {
+        if used_values.is_empty() {
+            return;
+        }
+
+        let status = response.status().as_u16();
+        let retry_after = response.headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+
+        for used in used_values {
+            if self.round_robin.record_response(&used.key, &used.value, &used.quota, status, retry_after) {
+                warn!("Marked replacement value for {} as exhausted after upstream status {}", used.key, status);
+            }
+        }
+    }
+
+    async fn apply_header_operations(
+        &self,
+        headers: &mut HeaderMap,
+        rule: &ForwardingRule,
+        compiled: &CompiledRule,
+        direction: &str,
+        ctx: &RequestContext,
+        used: &mut Vec<UsedValue>,
+    ) -> anyhow::Result<()> {
+        let operations = if direction == "request" { &rule.request_headers } else { &rule.response_headers };
+        for operation in operations {
+            // Check first so that an existing header does not use up a rotation slot or quota.
+            if operation.op == HeaderOperationKind::SetIfAbsent && headers.contains_key(operation.name.as_str()) {
+                continue;
+            }
+
+            let value = match (&operation.value, &operation.content) {
+                (Some(value), _) => Some(value.clone()),
+                (None, Some(source)) => {
+                    let key = format!("{}:{}:{}", rule.name, direction, operation.name);
+                    match self.resolve_replacement(&key, source, compiled, ctx, false, used).await? {
+                        Some(value) => Some(value),
+                        // An empty source leaves the header untouched, as with header_replacements.
+                        None => continue,
+                    }
+                }
+                (None, None) => None,
+            };
+
+            headers::apply_header_operation(headers, operation, value.as_deref())?;
+        }
         Ok(())
     }
 
-    async fn apply_header_replacements(&self, request: &mut Request, rule: &ForwardingRule) -> anyhow::Result<()> {
+    /// Selects the next value from `source`, rendering its template if it has one
+    /// (as a regex replacement if `expand_captures` is set). Values from sources
+    /// with a quota are recorded in `used`. If the content cannot be loaded, the
+    /// source's `on_error` policy decides the outcome.
+    async fn resolve_replacement(
+        &self,
+        key: &str,
+        source: &ContentSource,
+        compiled: &CompiledRule,
+        ctx: &RequestContext,
+        expand_captures: bool,
+        used: &mut Vec<UsedValue>,
+    ) -> anyhow::Result<Option<String>> {
+        let content = match self.content_manager.get_content(source).await {
+            Ok(content) => content,
+            Err(e) => match (source.on_error, &source.fallback_value) {
+                (ContentErrorPolicy::Skip, _) => {
+                    warn!("Skipping replacement {}: {}", key, e);
+                    return Ok(None);
+                }
+                (ContentErrorPolicy::Fallback, Some(fallback)) => {
+                    warn!("Using fallback value for {}: {}", key, e);
+                    return Ok(Some(render_value(fallback, source, compiled, ctx, expand_captures)?));
+                }
+                _ => return Err(e),
+            },
+        };
+
+        let value = match self.round_robin.select_replacement_content(key, &content, source, ctx) {
+            Some(value) => value,
+            None if source.quota.is_some() && !content.is_empty() => {
+                return Err(ContentExhausted { key: key.to_string() }.into());
+            }
+            None => return Ok(None),
+        };
+
+        if let Some(quota) = &source.quota {
+            used.push(UsedValue {
+                key: key.to_string(),
+                value: value.clone(),
+                quota: quota.clone(),
+            });
+        }
+
+        Ok(Some(render_value(&value, source, compiled, ctx, expand_captures)?))
+    }
+
+    async fn apply_header_replacements(
+        &self,
+        request: &mut Request,
+        rule: &ForwardingRule,
+        compiled: &CompiledRule,
+        ctx: &RequestContext,
+        used: &mut Vec<UsedValue>,
+    ) -> anyhow::Result<()> {
         let headers = request.headers_mut();
         
         for (header_name, content_source) in &rule.header_replacements {
-            let content = self.content_manager.get_content(content_source).await?;
-            
-            if let Some(replacement) = self.round_robin.select_replacement_content(
+            if let Some(replacement) = self.resolve_replacement(
                 &format!("{}:{}", rule.name, header_name),
-                &content
-            ) {
+                content_source,
+                compiled,
+                ctx,
+                false,
+                used,
+            ).await? {
                 if let (Ok(name), Ok(value)) = (
                     HeaderName::from_str(header_name),
                     HeaderValue::from_str(&replacement)
                 ) {
                     headers.insert(name, value);
                 } else {
-                    warn!("Invalid header name or value: {} = {}", header_name, replacement);
+                    let shown = if content_source.secret { fingerprint(&replacement) } else { replacement };
+                    warn!("Invalid header name or value: {} = {}", header_name, shown);
                 }
             }
         }
@@ -102,33 +495,99 @@ impl ProxyEngine {
         Ok(())
     }
 
-    async fn apply_body_replacements(&self, request: &mut Request, rule: &ForwardingRule) -> anyhow::Result<()> {
-        if rule.body_replacements.is_empty() {
-            return Ok(());
+    /// Applies the rule's body replacements for `direction`, removing and
+    /// restoring the body's `Content-Encoding` around them. Returns the
+    /// quota-tracked values that were used.
+    async fn apply_body_replacements(
+        &self,
+        headers: &mut HeaderMap,
+        body: &mut axum::body::Body,
+        rule: &ForwardingRule,
+        compiled: &CompiledRule,
+        direction: &str,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Vec<UsedValue>> {
+        let (body_replacements, scope) = if direction == "request" {
+            (&rule.body_replacements, "body")
+        } else {
+            (&rule.response_body_replacements, "response_body")
+        };
+        let mut used = Vec::new();
+        if body_replacements.is_empty() {
+            return Ok(used);
         }
 
-        let body = std::mem::replace(request.body_mut(), axum::body::Body::empty());
-        let body_bytes = axum::body::to_bytes(body, usize::MAX).await
-            .map_err(|e| anyhow::anyhow!("Failed to read request body: {}", e))?;
-        
-        let mut body_string = String::from_utf8_lossy(&body_bytes).to_string();
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
 
-        for (pattern, content_source) in &rule.body_replacements {
-            let content = self.content_manager.get_content(content_source).await?;
-            
-            if let Some(replacement) = self.round_robin.select_replacement_content(
-                &format!("{}:body:{}", rule.name, pattern),
-                &content
-            ) {
-                body_string = body_string.replace(pattern, &replacement);
+        if !body::is_replaceable(content_type.as_deref(), &rule.replace_content_types) {
+            debug!("Skipping {} body replacements for content type {:?}", direction, content_type);
+            return Ok(used);
+        }
+
+        let codings = match headers.get(CONTENT_ENCODING) {
+            Some(value) => match value.to_str().ok().and_then(encoding::parse_content_encoding) {
+                Some(codings) => codings,
+                None => {
+                    warn!("Skipping {} body replacements for unsupported Content-Encoding {:?}", direction, value);
+                    return Ok(used);
+                }
+            },
+            None => Vec::new(),
+        };
+
+        let raw_body = std::mem::replace(body, axum::body::Body::empty());
+        let raw_bytes = axum::body::to_bytes(raw_body, usize::MAX).await
+            .map_err(|e| anyhow::anyhow!("Failed to read {} body: {}", direction, e))?;
+
+        let body_bytes = match encoding::decode(&raw_bytes, &codings, rule.max_replacement_body_size) {
+            Ok(decoded) => decoded,
+            Err(e) if e.is::<BodyTooLarge>() => return Err(e),
+            Err(e) => {
+                warn!("Skipping {} body replacements, failed to decode {:?} body: {}", direction, codings, e);
+                *body = axum::body::Body::from(raw_bytes);
+                return Ok(used);
+            }
+        };
+
+        let mut replacements = Vec::with_capacity(body_replacements.len());
+        for (pattern, body_replacement) in body_replacements {
+            // Only a rule's own template may refer to regex captures.
+            let expand_captures = body_replacement.mode == ReplacementMode::Regex
+                && body_replacement.content.template.is_some();
+            if let Some(value) = self.resolve_replacement(
+                &format!("{}:{}:{}", rule.name, scope, pattern),
+                &body_replacement.content,
+                compiled,
+                ctx,
+                expand_captures,
+                &mut used,
+            ).await? {
+                replacements.push(ResolvedReplacement {
+                    pattern: compiled.body_pattern(pattern, body_replacement.mode)?,
+                    value,
+                    expand_captures,
+                });
             }
         }
 
-        *request.body_mut() = axum::body::Body::from(body_string);
-        Ok(())
+        let new_body = body::rewrite_body(
+            &body_bytes,
+            content_type.as_deref(),
+            &rule.replace_content_types,
+            &replacements,
+        )?;
+        let new_body = encoding::encode(&new_body, &codings)?;
+
+        // The length of the rewritten body is set again when it is sent.
+        headers.remove(CONTENT_LENGTH);
+        *body = axum::body::Body::from(new_body);
+        Ok(used)
     }
 
-    async fn forward_request(&self, request: Request, target_url: &str) -> anyhow::Result<Response> {
+    async fn forward_request(&self, request: Request, rule: &ForwardingRule, target_url: &str) -> anyhow::Result<Response> {
         let method = request.method().clone();
         let uri = request.uri().clone();
         let headers = request.headers().clone();
@@ -136,13 +595,14 @@ impl ProxyEngine {
 
         let full_url = format!("{}{}", target_url.trim_end_matches('/'), uri.path_and_query().map(|pq| pq.as_str()).unwrap_or(""));
 
+        let client = self.clients.get(&rule.name);
         let mut req_builder = match method {
-            Method::GET => self.client.get(&full_url),
-            Method::POST => self.client.post(&full_url),
-            Method::PUT => self.client.put(&full_url),
-            Method::DELETE => self.client.delete(&full_url),
-            Method::PATCH => self.client.patch(&full_url),
-            Method::HEAD => self.client.head(&full_url),
+            Method::GET => client.get(&full_url),
+            Method::POST => client.post(&full_url),
+            Method::PUT => client.put(&full_url),
+            Method::DELETE => client.delete(&full_url),
+            Method::PATCH => client.patch(&full_url),
+            Method::HEAD => client.head(&full_url),
             _ => return Err(anyhow::anyhow!("Unsupported HTTP method: {}", method)),
         };
 
@@ -156,8 +616,15 @@ impl ProxyEngine {
             req_builder = req_builder.body(body);
         }
 
-        let response = req_builder.send().await?;
-        
+        let timed_out = |e: reqwest::Error| -> anyhow::Error {
+            match UpstreamTimeout::from_reqwest(&e, target_url) {
+                Some(timeout) => timeout.into(),
+                None => e.into(),
+            }
+        };
+
+        let mut response = req_builder.send().await.map_err(timed_out)?;
+
         let mut response_builder = Response::builder()
             .status(response.status().as_u16());
 
@@ -167,12 +634,86 @@ impl ProxyEngine {
             }
         }
 
-        let body_bytes = response.bytes().await?;
+        let idle_timeout = rule.upstream.as_ref()
+            .and_then(|upstream| upstream.idle_timeout_ms)
+            .map(Duration::from_millis);
+        let mut body_bytes = Vec::new();
+        loop {
+            let chunk = match idle_timeout {
+                Some(idle) => tokio::time::timeout(idle, response.chunk()).await
+                    .map_err(|_| UpstreamTimeout { target: target_url.to_string(), phase: TimeoutPhase::Idle })?,
+                None => response.chunk().await,
+            };
+            match chunk.map_err(timed_out)? {
+                Some(chunk) => body_bytes.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
         let final_response = response_builder.body(axum::body::Body::from(body_bytes))?;
 
         Ok(final_response)
     }
 
+    pub fn content_usage(&self) -> Vec<UsageSnapshot> {
+        self.round_robin.usage_snapshot()
+    }
+
+    pub fn rate_limit_snapshot(&self) -> Vec<BucketSnapshot> {
+        self.rate_limiter.snapshot()
+    }
+
+    pub fn circuit_snapshot(&self) -> Vec<CircuitSnapshot> {
+        self.breakers.snapshot()
+    }
+
+    /// Circuit state changes of all rule targets.
+    pub fn subscribe_circuit_transitions(&self) -> tokio::sync::broadcast::Receiver<CircuitTransition> {
+        self.breakers.subscribe()
+    }
+
+    /// Average response time of `target` for `rule`, in milliseconds.
+    pub fn target_latency_ms(&self, rule: &str, target: &str) -> Option<f64> {
+        self.balancer.latency_ms(rule, target)
+    }
+
+    pub fn target_in_flight(&self, rule: &str, target: &str) -> usize {
+        self.concurrency.in_flight(rule, target)
+    }
+
+    pub fn cleanup_rate_limits(&self) -> usize {
+        self.rate_limiter.cleanup()
+    }
+
+    /// Drops quota usage for replacements that were removed, values their
+    /// source no longer returns, and values whose limits have all reset.
+    pub async fn cleanup_quota_usage(&self) -> usize {
+        let mut scopes = HashMap::new();
+        for rule in self.get_rules().await {
+            let sources = rule.header_replacements.iter()
+                .map(|(name, source)| (format!("{}:{}", rule.name, name), source))
+                .chain(rule.request_headers.iter().filter_map(|operation| {
+                    operation.content.as_ref().map(|source| (format!("{}:request:{}", rule.name, operation.name), source))
+                }))
+                .chain(rule.response_headers.iter().filter_map(|operation| {
+                    operation.content.as_ref().map(|source| (format!("{}:response:{}", rule.name, operation.name), source))
+                }))
+                .chain(rule.body_replacements.iter()
+                    .map(|(pattern, replacement)| (format!("{}:body:{}", rule.name, pattern), &replacement.content)))
+                .chain(rule.response_body_replacements.iter()
+                    .map(|(pattern, replacement)| (format!("{}:response_body:{}", rule.name, pattern), &replacement.content)));
+
+            for (scope, source) in sources {
+                if let Some(quota) = &source.quota {
+                    scopes.insert(scope, LiveScope {
+                        quota: quota.clone(),
+                        values: self.content_manager.cached_content(source).await,
+                    });
+                }
+            }
+        }
+        self.round_robin.cleanup_quota_usage(&scopes)
+    }
+
     pub async fn get_rules(&self) -> Vec<ForwardingRule> {
         let router = self.router.read().await;
         router.get_all_rules().into_iter().cloned().collect()
@@ -182,13 +723,418 @@ impl ProxyEngine {
         self.content_manager.clear_cache().await;
     }
 
+    pub async fn cache_stats(&self) -> CacheStatsSnapshot {
+        self.content_manager.cache_stats().await
+    }
+
+    pub async fn content_source_info(&self, source: &ContentSource) -> Option<CacheEntryInfo> {
+        self.content_manager.cache_entry_info(source).await
+    }
+
+    pub async fn cleanup_expired_content(&self) -> usize {
+        self.content_manager.cleanup_expired().await
+    }
+
     pub async fn remove_content_from_cache(&self, source: &ContentSource) {
         self.content_manager.remove_from_cache(source).await;
     }
+
+    /// Removes cached responses that can no longer be served or revalidated,
+    /// returning how many URLs were removed.
+    pub async fn cleanup_expired_responses(&self) -> usize {
+        let mut removed = 0;
+        for rule in self.get_rules().await {
+            if let Some(cache_config) = &rule.cache {
+                removed += self.response_cache.remove_expired(&rule.name, cache_config).await;
+            }
+        }
+        removed
+    }
+
+    /// Purges cached responses of the named rule, or of every rule, whose
+    /// path starts with `url_prefix`. Returns how many URLs were purged.
+    pub async fn purge_response_cache(&self, rule_name: Option<&str>, url_prefix: Option<&str>) -> anyhow::Result<usize> {
+        let mut purged = 0;
+        for rule in self.get_rules().await {
+            if rule_name.is_some_and(|name| name != rule.name) {
+                continue;
+            }
+            if let Some(cache_config) = &rule.cache {
+                purged += self.response_cache.purge(&rule.name, cache_config, url_prefix).await?;
+            }
+        }
+        Ok(purged)
+    }
+
+    pub async fn refresh_content(&self, source: &ContentSource) -> anyhow::Result<ContentSet> {
+        self.content_manager.refresh(source).await
+    }
+
+    pub async fn preview_content(&self, source: &ContentSource) -> anyhow::Result<ContentSet> {
+        self.content_manager.get_content(source).await
+    }
+}
+
+/// Renders `value` with the source's template, parsed when the rule was loaded.
+fn render_value(
+    value: &str,
+    source: &ContentSource,
+    compiled: &CompiledRule,
+    ctx: &RequestContext,
+    expand_captures: bool,
+) -> anyhow::Result<String> {
+    match &source.template {
+        Some(template) if expand_captures => compiled.template(template)?.render_regex_replacement(value, ctx),
+        Some(template) => compiled.template(template)?.render(value, ctx),
+        None => Ok(value.to_string()),
+    }
+}
+
+/// Parses a `Retry-After` value, given either as seconds or as an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(std::time::SystemTime::now()).unwrap_or(Duration::ZERO))
 }
 
 impl Default for ProxyEngine {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::circuit_breaker::CircuitState;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_response_cache() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        let app = axum::Router::new().route("/static/*path", axum::routing::get(move |headers: HeaderMap| {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                if headers.get(IF_NONE_MATCH).is_some_and(|v| v == "\"v1\"") {
+                    return (StatusCode::NOT_MODIFIED, [("etag", "\"v1\"")], "").into_response();
+                }
+                (StatusCode::OK, [("etag", "\"v1\""), ("cache-control", "max-age=0")], "hello").into_response()
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let rule: ForwardingRule = toml::from_str(&format!(r#"
+            name = "static"
+            path = "/static/**"
+            target_urls = ["http://{}"]
+            load_balancing = "round_robin"
+            cache = {{ backend = "memory" }}
+        "#, address)).unwrap();
+        let engine = ProxyEngine::new();
+        engine.update_rules(vec![rule]).await.unwrap();
+
+        let get = |path: &str| Request::builder().uri(path).body(axum::body::Body::empty()).unwrap();
+        let x_cache = |response: &Response| response.headers()[response_cache::X_CACHE].to_str().unwrap().to_string();
+
+        let response = engine.handle_request(get("/static/a")).await.unwrap();
+        assert_eq!(x_cache(&response), "MISS");
+
+        // Stale at once (max-age=0), so it is revalidated with the stored ETag.
+        let response = engine.handle_request(get("/static/a")).await.unwrap();
+        assert_eq!((response.status(), x_cache(&response)), (StatusCode::OK, "REVALIDATED".to_string()));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"hello");
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        assert_eq!(engine.purge_response_cache(Some("static"), Some("/static/")).await.unwrap(), 1);
+        let response = engine.handle_request(get("/static/a")).await.unwrap();
+        assert_eq!(x_cache(&response), "MISS");
+    }
+
+    #[tokio::test]
+    async fn test_set_if_absent_keeps_rotation_for_missing_headers() {
+        let app = axum::Router::new().route("/echo", axum::routing::get(|headers: HeaderMap| async move {
+            headers["x-key"].to_str().unwrap().to_string()
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let rule: ForwardingRule = toml::from_str(&format!(r#"
+            name = "echo"
+            path = "/echo"
+            target_urls = ["http://{}"]
+            load_balancing = "round_robin"
+            request_headers = [
+                {{ op = "set_if_absent", name = "X-Key", content = {{ source = "inline", values = ["a", "b"] }} }},
+            ]
+        "#, address)).unwrap();
+        let engine = ProxyEngine::new();
+        engine.update_rules(vec![rule]).await.unwrap();
+
+        let body = |response: Response| async move {
+            axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        };
+        let request = Request::builder().uri("/echo").header("x-key", "mine").body(axum::body::Body::empty()).unwrap();
+        assert_eq!(&body(engine.handle_request(request).await.unwrap()).await[..], b"mine");
+
+        let request = Request::builder().uri("/echo").body(axum::body::Body::empty()).unwrap();
+        assert_eq!(&body(engine.handle_request(request).await.unwrap()).await[..], b"a");
+    }
+
+    #[tokio::test]
+    async fn test_content_error_policies() {
+        let app = axum::Router::new().route("/echo", axum::routing::get(|headers: HeaderMap| async move {
+            let header = |name: &str| headers.get(name).map_or("-", |v| v.to_str().unwrap()).to_string();
+            format!("{} {}", header("x-skipped"), header("x-fallback"))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let missing = std::env::temp_dir().join(format!("ultiproxy-missing-{}.txt", std::process::id()));
+        let rule = |name: &str, extra: &str| -> ForwardingRule {
+            toml::from_str(&format!(r#"
+                name = "{}"
+                path = "/echo"
+                target_urls = ["http://{}"]
+                load_balancing = "round_robin"
+                request_headers = [
+                    {{ op = "set", name = "X-Skipped", content = {{ source = "file", path = '{}', on_error = "skip" }} }},
+                    {{ op = "set", name = "X-Fallback", content = {{ source = "file", path = '{}', on_error = "fallback", fallback_value = "spare" }} }},
+                    {}
+                ]
+            "#, name, address, missing.display(), missing.display(), extra)).unwrap()
+        };
+        let get = || Request::builder().uri("/echo").body(axum::body::Body::empty()).unwrap();
+
+        let engine = ProxyEngine::new();
+        engine.update_rules(vec![rule("lenient", "")]).await.unwrap();
+        let response = engine.handle_request(get()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"- spare");
+
+        let failing = format!(r#"{{ op = "set", name = "X-Failed", content = {{ source = "file", path = '{}' }} }}"#, missing.display());
+        engine.update_rules(vec![rule("strict", &failing)]).await.unwrap();
+        let status = match engine.handle_request(get()).await {
+            Ok(response) => response.status(),
+            Err(status) => status,
+        };
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_body_replacements() {
+        use crate::proxy::encoding::ContentCoding;
+
+        // Echoes the body back with the request's content headers.
+        let app = axum::Router::new().route("/echo", axum::routing::post(|headers: HeaderMap, body: axum::body::Bytes| async move {
+            let mut echoed = HeaderMap::new();
+            for name in [CONTENT_TYPE, CONTENT_ENCODING] {
+                if let Some(value) = headers.get(&name) {
+                    echoed.insert(name, value.clone());
+                }
+            }
+            (echoed, body)
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let rule: ForwardingRule = toml::from_str(&format!(r#"
+            name = "echo"
+            path = "/echo"
+            target_urls = ["http://{}"]
+            load_balancing = "round_robin"
+            max_replacement_body_size = 4096
+            body_replacements = {{ "{{{{KEY}}}}" = {{ source = "inline", values = ["k1"] }} }}
+            response_body_replacements = {{ "k1" = {{ source = "inline", values = ["****"] }} }}
+        "#, address)).unwrap();
+        let engine = ProxyEngine::new();
+        engine.update_rules(vec![rule]).await.unwrap();
+
+        let post = |body: Vec<u8>, coding: &str| Request::builder()
+            .method(Method::POST)
+            .uri("/echo")
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_ENCODING, coding)
+            .body(axum::body::Body::from(body))
+            .unwrap();
+
+        for (coding, name) in [(ContentCoding::Gzip, "gzip"), (ContentCoding::Deflate, "deflate"), (ContentCoding::Brotli, "br"), (ContentCoding::Zstd, "zstd")] {
+            let body = encoding::encode(br#"{"key":"{{KEY}}"}"#, &[coding]).unwrap();
+            let response = engine.handle_request(post(body, name)).await.unwrap();
+            assert_eq!(response.headers()[CONTENT_ENCODING], name);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            // Replaced on the way in ({{KEY}} -> k1) and on the way out (k1 -> ****).
+            assert_eq!(encoding::decode(&body, &[coding], 4096).unwrap(), br#"{"key":"****"}"#);
+        }
+
+        let bomb = encoding::encode(&vec![b' '; 1024 * 1024], &[ContentCoding::Gzip]).unwrap();
+        assert_eq!(engine.handle_request(post(bomb, "gzip")).await.unwrap_err(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+
+        let later = httpdate::fmt_http_date(std::time::SystemTime::now() + Duration::from_secs(600));
+        let wait = parse_retry_after(&later).unwrap();
+        assert!(wait > Duration::from_secs(590) && wait <= Duration::from_secs(600));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let app = axum::Router::new().route("/limited", axum::routing::get(|| async { "ok" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let rule: ForwardingRule = toml::from_str(&format!(r#"
+            name = "limited"
+            path = "/limited"
+            target_urls = ["http://{}"]
+            load_balancing = "round_robin"
+            rate_limit = {{ requests = 1, period = 60, key = {{ type = "global" }} }}
+        "#, address)).unwrap();
+        let engine = ProxyEngine::new();
+        engine.update_rules(vec![rule]).await.unwrap();
+
+        let get = || Request::builder().uri("/limited").body(axum::body::Body::empty()).unwrap();
+        let response = engine.handle_request(get()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+
+        let response = engine.handle_request(get()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+        assert_eq!(engine.rate_limit_snapshot().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sticky_sessions() {
+        let mut targets = Vec::new();
+        for _ in 0..2 {
+            let app = axum::Router::new().route("/app", axum::routing::get(|| async { "ok" }));
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            targets.push(format!("http://{}", listener.local_addr().unwrap()));
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        }
+
+        let rule: ForwardingRule = toml::from_str(&format!(r#"
+            name = "app"
+            path = "/app"
+            target_urls = ["{}", "{}"]
+            load_balancing = "consistent_hash"
+            consistent_hash = {{ key = {{ type = "header", name = "X-User" }} }}
+            sticky = {{ max_age = 60 }}
+        "#, targets[0], targets[1])).unwrap();
+        let engine = ProxyEngine::new();
+        engine.update_rules(vec![rule]).await.unwrap();
+
+        let cookie = format!("ultiproxy_affinity={}", hashing::target_id(&targets[1]));
+        let request = |cookie: &str| {
+            Request::builder().uri("/app").header("x-user", "alice").header("cookie", cookie)
+                .body(axum::body::Body::empty()).unwrap()
+        };
+        for _ in 0..3 {
+            let response = engine.handle_request(request(&cookie)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(!response.headers().contains_key(SET_COOKIE));
+        }
+
+        // An unknown target is rebalanced and pinned anew.
+        let response = engine.handle_request(request("ultiproxy_affinity=gone")).await.unwrap();
+        let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.starts_with("ultiproxy_affinity=") && set_cookie.ends_with("Max-Age=60"));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_skips_failing_target() {
+        let app = axum::Router::new().route("/svc", axum::routing::get(|| async { "ok" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        // Nothing listens on a port that was just released.
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+
+        let rule: ForwardingRule = toml::from_str(&format!(r#"
+            name = "svc"
+            path = "/svc"
+            target_urls = ["{}", "{}"]
+            load_balancing = "round_robin"
+            circuit_breaker = {{ consecutive_failures = 1, open_duration = 60 }}
+        "#, live, dead)).unwrap();
+        let engine = ProxyEngine::new();
+        engine.update_rules(vec![rule]).await.unwrap();
+        let mut transitions = engine.subscribe_circuit_transitions();
+
+        let get = || Request::builder().uri("/svc").body(axum::body::Body::empty()).unwrap();
+        for _ in 0..2 {
+            let _ = engine.handle_request(get()).await;
+        }
+        for _ in 0..4 {
+            assert_eq!(engine.handle_request(get()).await.unwrap().status(), StatusCode::OK);
+        }
+
+        let transition = transitions.try_recv().unwrap();
+        assert_eq!((transition.target.as_str(), transition.to), (dead.as_str(), CircuitState::Open));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_timeouts_return_gateway_timeout() {
+        let app = axum::Router::new()
+            .route("/fast", axum::routing::get(|| async { "ok" }))
+            .route("/slow", axum::routing::get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "late"
+            }))
+            .route("/stall", axum::routing::get(|| async {
+                let chunks = futures::stream::once(async { Ok::<_, std::io::Error>("first") })
+                    .chain(futures::stream::once(async {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        Ok("second")
+                    }));
+                axum::body::Body::from_stream(chunks)
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let rule = |name: &str, upstream_config: &str| -> ForwardingRule {
+            toml::from_str(&format!(r#"
+                name = "{}"
+                path = "/{}"
+                target_urls = ["{}"]
+                load_balancing = "round_robin"
+                upstream = {{ {} }}
+            "#, name, name, upstream, upstream_config)).unwrap()
+        };
+        let engine = ProxyEngine::new();
+        engine.update_rules(vec![
+            rule("fast", "request_timeout_ms = 1000, pool_max_idle_per_host = 2, tcp_keepalive_ms = 30000"),
+            rule("slow", "request_timeout_ms = 100"),
+            rule("stall", "idle_timeout_ms = 100"),
+        ]).await.unwrap();
+
+        let get = |path: &str| Request::builder().uri(path).body(axum::body::Body::empty()).unwrap();
+        assert_eq!(engine.handle_request(get("/fast")).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(engine.handle_request(get("/slow")).await.unwrap_err(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(engine.handle_request(get("/stall")).await.unwrap_err(), StatusCode::GATEWAY_TIMEOUT);
+    }
+}
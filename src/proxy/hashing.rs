@@ -0,0 +1,202 @@
+use crate::config::{ConsistentHashConfig, HashAlgorithm, HashKey};
+use crate::proxy::template::RequestContext;
+use axum::http::header::COOKIE;
+use axum::http::HeaderMap;
+use sha2::{Digest, Sha256};
+
+/// Size of the Maglev lookup table; a prime well above any target count.
+const MAGLEV_TABLE_SIZE: u64 = 65_537;
+
+/// Maps hashes to target indices so that changing the target list only
+/// remaps a small share of keys.
+#[derive(Debug)]
+pub struct HashTable {
+    /// What the table was built from, to detect when it is out of date.
+    targets: Vec<String>,
+    algorithm: HashAlgorithm,
+    virtual_nodes: usize,
+    lookup: Lookup,
+}
+
+#[derive(Debug)]
+enum Lookup {
+    /// Points sorted by hash; a key goes to the first point at or after its hash.
+    Ring(Vec<(u64, usize)>),
+    Maglev(Vec<u32>),
+}
+
+impl HashTable {
+    pub fn new(targets: &[String], config: &ConsistentHashConfig) -> Self {
+        let lookup = match config.algorithm {
+            HashAlgorithm::Ring => {
+                let mut points: Vec<(u64, usize)> = targets.iter()
+                    .enumerate()
+                    .flat_map(|(index, target)| {
+                        (0..config.virtual_nodes).map(move |node| (hash64(&format!("{}#{}", target, node)), index))
+                    })
+                    .collect();
+                points.sort_unstable();
+                Lookup::Ring(points)
+            }
+            HashAlgorithm::Maglev => Lookup::Maglev(maglev_table(targets)),
+        };
+
+        Self {
+            targets: targets.to_vec(),
+            algorithm: config.algorithm,
+            virtual_nodes: config.virtual_nodes,
+            lookup,
+        }
+    }
+
+    pub fn is_current(&self, targets: &[String], config: &ConsistentHashConfig) -> bool {
+        self.targets == targets && self.algorithm == config.algorithm && self.virtual_nodes == config.virtual_nodes
+    }
+
+    /// Index of the target `key` maps to.
+    pub fn get(&self, key: &str) -> Option<usize> {
+        let hash = hash64(key);
+        match &self.lookup {
+            Lookup::Ring(points) => {
+                let position = points.partition_point(|(point, _)| *point < hash);
+                points.get(position).or_else(|| points.first()).map(|(_, index)| *index)
+            }
+            Lookup::Maglev(table) => table.get((hash % table.len() as u64) as usize).map(|index| *index as usize),
+        }
+    }
+}
+
+/// Builds the lookup table of Maglev (Eisenbud et al., NSDI 2016): each
+/// target fills its preferred free slots in turn, following its own
+/// permutation of the table.
+fn maglev_table(targets: &[String]) -> Vec<u32> {
+    if targets.is_empty() {
+        return Vec::new();
+    }
+
+    let size = MAGLEV_TABLE_SIZE;
+    let permutations: Vec<(u64, u64)> = targets.iter()
+        .map(|target| {
+            let offset = hash64(&format!("{}#offset", target)) % size;
+            let skip = hash64(&format!("{}#skip", target)) % (size - 1) + 1;
+            (offset, skip)
+        })
+        .collect();
+
+    let mut table = vec![u32::MAX; size as usize];
+    let mut next = vec![0u64; targets.len()];
+    let mut filled = 0;
+    loop {
+        for (index, (offset, skip)) in permutations.iter().enumerate() {
+            let mut slot = (offset + next[index] * skip) % size;
+            while table[slot as usize] != u32::MAX {
+                next[index] += 1;
+                slot = (offset + next[index] * skip) % size;
+            }
+            table[slot as usize] = index as u32;
+            next[index] += 1;
+            filled += 1;
+            if filled == size {
+                return table;
+            }
+        }
+    }
+}
+
+/// The value hashed for a request: its configured key, or the client IP
+/// when the request does not carry it.
+pub fn request_key(key: &HashKey, ctx: &RequestContext) -> String {
+    let value = match key {
+        HashKey::Ip => None,
+        HashKey::Header { name } => ctx.headers.get(name.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        HashKey::Cookie { name } => cookie(&ctx.headers, name),
+        HashKey::Capture { name } => ctx.captures.get(name).cloned(),
+    };
+    value.unwrap_or_else(|| ctx.client_ip.map(|ip| ip.to_string()).unwrap_or_default())
+}
+
+/// Value of the cookie `name` in the request's `Cookie` headers.
+pub fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers.get_all(COOKIE).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value.trim_matches('"').to_string())
+}
+
+/// Opaque, stable identifier of a target for affinity cookies, so the
+/// cookie does not reveal backend addresses.
+pub fn target_id(target: &str) -> String {
+    format!("{:016x}", hash64(target))
+}
+
+/// A hash that stays the same across builds and restarts, unlike `DefaultHasher`.
+pub(crate) fn hash64(data: &str) -> u64 {
+    let digest = Sha256::digest(data.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().expect("digest is longer than 8 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use std::collections::HashMap;
+
+    fn targets(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("http://backend-{}", i)).collect()
+    }
+
+    /// Share of keys that map to a different target once a fifth target is added.
+    fn remapped_share(algorithm: HashAlgorithm) -> f64 {
+        let config = ConsistentHashConfig { algorithm, virtual_nodes: 160, ..Default::default() };
+        let (before, after) = (targets(4), targets(5));
+        let (old, new) = (HashTable::new(&before, &config), HashTable::new(&after, &config));
+
+        let keys = 2000;
+        let moved = (0..keys)
+            .filter(|key| {
+                let key = format!("user-{}", key);
+                before[old.get(&key).unwrap()] != after[new.get(&key).unwrap()]
+            })
+            .count();
+        moved as f64 / keys as f64
+    }
+
+    #[test]
+    fn test_adding_a_target_remaps_few_keys() {
+        // Ideally 1/5 of the keys move, all of them to the new target.
+        assert!(remapped_share(HashAlgorithm::Ring) < 0.3);
+        assert!(remapped_share(HashAlgorithm::Maglev) < 0.3);
+    }
+
+    #[test]
+    fn test_maglev_spreads_evenly() {
+        let table = maglev_table(&targets(3));
+        let mut counts = [0usize; 3];
+        for index in table {
+            counts[index as usize] += 1;
+        }
+        let expected = MAGLEV_TABLE_SIZE as usize / 3;
+        assert!(counts.iter().all(|count| count.abs_diff(expected) <= 1));
+    }
+
+    #[test]
+    fn test_request_key() {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_static("theme=dark; session=abc"));
+        let ctx = RequestContext {
+            client_ip: Some("10.0.0.7".parse().unwrap()),
+            headers,
+            captures: HashMap::from([("tenant".to_string(), "acme".to_string())]),
+            ..Default::default()
+        };
+
+        assert_eq!(request_key(&HashKey::Ip, &ctx), "10.0.0.7");
+        assert_eq!(request_key(&HashKey::Cookie { name: "session".to_string() }, &ctx), "abc");
+        assert_eq!(request_key(&HashKey::Capture { name: "tenant".to_string() }, &ctx), "acme");
+        assert_eq!(request_key(&HashKey::Header { name: "X-User".to_string() }, &ctx), "10.0.0.7");
+    }
+}
@@ -0,0 +1,108 @@
+use crate::config::{HeaderOperation, HeaderOperationKind};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use std::str::FromStr;
+
+/// Applies a header operation. `value` is the resolved value for operations
+/// that write one, and is ignored by `remove` and `rename`.
+pub fn apply_header_operation(
+    headers: &mut HeaderMap,
+    operation: &HeaderOperation,
+    value: Option<&str>,
+) -> anyhow::Result<()> {
+    let name = HeaderName::from_str(&operation.name)?;
+    let parse_value = || -> anyhow::Result<HeaderValue> {
+        let value = value.ok_or_else(|| anyhow::anyhow!("no value for header '{}'", operation.name))?;
+        HeaderValue::from_str(value)
+            .map_err(|e| anyhow::anyhow!("invalid value for header '{}': {}", operation.name, e))
+    };
+
+    match operation.op {
+        HeaderOperationKind::Remove => {
+            headers.remove(&name);
+        }
+        HeaderOperationKind::Set => {
+            headers.insert(name, parse_value()?);
+        }
+        HeaderOperationKind::Append => {
+            headers.append(name, parse_value()?);
+        }
+        HeaderOperationKind::SetIfAbsent => {
+            if !headers.contains_key(&name) {
+                headers.insert(name, parse_value()?);
+            }
+        }
+        HeaderOperationKind::Rename => {
+            let to = operation.to.as_deref()
+                .ok_or_else(|| anyhow::anyhow!("rename of '{}' requires 'to'", operation.name))?;
+            let to = HeaderName::from_str(to)?;
+            let values: Vec<HeaderValue> = match headers.entry(name) {
+                axum::http::header::Entry::Occupied(entry) => entry.remove_entry_mult().1.collect(),
+                axum::http::header::Entry::Vacant(_) => Vec::new(),
+            };
+            for value in values {
+                headers.append(to.clone(), value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(op: HeaderOperationKind, name: &str, to: Option<&str>) -> HeaderOperation {
+        HeaderOperation {
+            op,
+            name: name.to_string(),
+            value: None,
+            content: None,
+            to: to.map(str::to_string),
+        }
+    }
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-a", HeaderValue::from_static("1"));
+        headers.append("x-b", HeaderValue::from_static("2"));
+        headers.append("x-b", HeaderValue::from_static("3"));
+        headers
+    }
+
+    #[test]
+    fn test_remove_and_set() {
+        let mut headers = headers();
+        apply_header_operation(&mut headers, &operation(HeaderOperationKind::Remove, "X-A", None), None).unwrap();
+        assert!(!headers.contains_key("x-a"));
+
+        apply_header_operation(&mut headers, &operation(HeaderOperationKind::Set, "x-b", None), Some("9")).unwrap();
+        assert_eq!(headers.get_all("x-b").iter().collect::<Vec<_>>(), vec!["9"]);
+    }
+
+    #[test]
+    fn test_append_and_set_if_absent() {
+        let mut headers = headers();
+        apply_header_operation(&mut headers, &operation(HeaderOperationKind::Append, "x-a", None), Some("2")).unwrap();
+        assert_eq!(headers.get_all("x-a").iter().collect::<Vec<_>>(), vec!["1", "2"]);
+
+        apply_header_operation(&mut headers, &operation(HeaderOperationKind::SetIfAbsent, "x-a", None), Some("9")).unwrap();
+        apply_header_operation(&mut headers, &operation(HeaderOperationKind::SetIfAbsent, "x-c", None), Some("9")).unwrap();
+        assert_eq!(headers.get("x-a").unwrap(), "1");
+        assert_eq!(headers.get("x-c").unwrap(), "9");
+    }
+
+    #[test]
+    fn test_rename_keeps_all_values() {
+        let mut headers = headers();
+        apply_header_operation(&mut headers, &operation(HeaderOperationKind::Rename, "x-b", Some("x-renamed")), None).unwrap();
+        assert!(!headers.contains_key("x-b"));
+        assert_eq!(headers.get_all("x-renamed").iter().collect::<Vec<_>>(), vec!["2", "3"]);
+    }
+
+    #[test]
+    fn test_set_without_value_fails() {
+        let mut headers = headers();
+        assert!(apply_header_operation(&mut headers, &operation(HeaderOperationKind::Set, "x-a", None), None).is_err());
+    }
+}
@@ -1,6 +1,20 @@
+pub mod balancer;
+pub mod body;
+pub mod circuit_breaker;
+pub mod concurrency;
+pub mod encoding;
 pub mod engine;
+pub mod hashing;
+pub mod headers;
+pub mod quota;
+pub mod rate_limit;
+pub mod replacement;
+pub mod response_cache;
 pub mod router;
 pub mod round_robin;
+pub mod selection;
+pub mod template;
+pub mod upstream;
 
 pub use engine::*;
 pub use router::*;
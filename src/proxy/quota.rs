@@ -0,0 +1,300 @@
+use crate::config::QuotaConfig;
+use crate::content::{ContentSet, ContentValue};
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Per-value usage counters for replacement content, keyed by replacement
+/// scope (`rule:header` or `rule:body:pattern`) and value.
+#[derive(Debug, Default)]
+pub struct QuotaTracker {
+    usage: DashMap<(String, ContentValue), ValueUsage>,
+}
+
+#[derive(Debug, Clone)]
+struct ValueUsage {
+    window_start: Instant,
+    window_count: u32,
+    day: u64,
+    day_count: u64,
+    total_count: u64,
+    exhausted_until: Option<Instant>,
+    last_status: Option<u16>,
+}
+
+impl ValueUsage {
+    fn new() -> Self {
+        Self {
+            window_start: Instant::now(),
+            window_count: 0,
+            day: current_day(),
+            day_count: 0,
+            total_count: 0,
+            exhausted_until: None,
+            last_status: None,
+        }
+    }
+
+    /// Rolls the rate window and daily counter forward if they have elapsed.
+    fn refresh(&mut self, quota: Option<&QuotaConfig>, now: Instant) {
+        let window = Duration::from_secs(quota.map(|q| q.rate_window).unwrap_or(60));
+        if now.duration_since(self.window_start) >= window {
+            self.window_start = now;
+            self.window_count = 0;
+        }
+
+        let today = current_day();
+        if today != self.day {
+            self.day = today;
+            self.day_count = 0;
+        }
+
+        if self.exhausted_until.is_some_and(|until| now >= until) {
+            self.exhausted_until = None;
+        }
+    }
+
+    /// Whether the counters have all reset, so the entry holds nothing that
+    /// a new one would not.
+    fn is_idle(&self) -> bool {
+        self.window_count == 0 && self.day_count == 0 && self.exhausted_until.is_none()
+    }
+
+    fn has_capacity(&self, quota: Option<&QuotaConfig>) -> bool {
+        if self.exhausted_until.is_some() {
+            return false;
+        }
+
+        match quota {
+            Some(quota) => {
+                quota.rate_limit.is_none_or(|limit| self.window_count < limit)
+                    && quota.daily_quota.is_none_or(|limit| self.day_count < limit)
+            }
+            None => true,
+        }
+    }
+}
+
+/// Returned when every value of a quota-limited source is exhausted.
+#[derive(Debug)]
+pub struct ContentExhausted {
+    pub key: String,
+}
+
+impl std::fmt::Display for ContentExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "all replacement values for {} are exhausted", self.key)
+    }
+}
+
+impl std::error::Error for ContentExhausted {}
+
+/// A replacement scope that is still configured with a quota.
+#[derive(Debug, Clone)]
+pub struct LiveScope {
+    pub quota: QuotaConfig,
+    /// The source's current values, if they are cached.
+    pub values: Option<ContentSet>,
+}
+
+/// A point-in-time view of one value's usage.
+#[derive(Debug, Clone)]
+pub struct UsageSnapshot {
+    pub scope: String,
+    pub value: ContentValue,
+    pub window_count: u32,
+    pub day_count: u64,
+    pub total_count: u64,
+    pub exhausted_for: Option<Duration>,
+    pub last_status: Option<u16>,
+}
+
+impl QuotaTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether `value` can be used now without exceeding its limits.
+    pub fn is_available(&self, scope: &str, value: &ContentValue, quota: Option<&QuotaConfig>) -> bool {
+        let key = (scope.to_string(), value.clone());
+        match self.usage.get_mut(&key) {
+            Some(mut usage) => {
+                usage.refresh(quota, Instant::now());
+                usage.has_capacity(quota)
+            }
+            None => true,
+        }
+    }
+
+    /// Records a use of `value` if it still has capacity, returning whether it did.
+    pub fn try_acquire(&self, scope: &str, value: &ContentValue, quota: Option<&QuotaConfig>) -> bool {
+        let mut usage = self.usage
+            .entry((scope.to_string(), value.clone()))
+            .or_insert_with(ValueUsage::new);
+
+        usage.refresh(quota, Instant::now());
+        if !usage.has_capacity(quota) {
+            return false;
+        }
+
+        usage.window_count += 1;
+        usage.day_count += 1;
+        usage.total_count += 1;
+        true
+    }
+
+    /// Records the upstream status for a request that used `value`, benching
+    /// the value when the status is one of the quota's `exhaust_on` codes.
+    pub fn record_response(
+        &self,
+        scope: &str,
+        value: &ContentValue,
+        quota: &QuotaConfig,
+        status: u16,
+        retry_after: Option<Duration>,
+    ) -> bool {
+        let mut usage = self.usage
+            .entry((scope.to_string(), value.clone()))
+            .or_insert_with(ValueUsage::new);
+
+        usage.last_status = Some(status);
+        if !quota.exhaust_on.contains(&status) {
+            return false;
+        }
+
+        let cooldown = retry_after.unwrap_or(Duration::from_secs(quota.cooldown));
+        usage.exhausted_until = Some(Instant::now() + cooldown);
+        true
+    }
+
+    /// Drops usage of scopes that are no longer in `scopes`, of values their
+    /// source no longer has, and entries whose windows have all expired.
+    /// Returns how many entries were removed.
+    pub fn cleanup(&self, scopes: &HashMap<String, LiveScope>) -> usize {
+        let now = Instant::now();
+        let before = self.usage.len();
+        self.usage.retain(|(scope, value), usage| {
+            let Some(live) = scopes.get(scope) else {
+                return false;
+            };
+            if live.values.as_ref().is_some_and(|content| !content.values.contains(value)) {
+                return false;
+            }
+            usage.refresh(Some(&live.quota), now);
+            !usage.is_idle()
+        });
+        before.saturating_sub(self.usage.len())
+    }
+
+    pub fn snapshot(&self) -> Vec<UsageSnapshot> {
+        let now = Instant::now();
+        let mut snapshot: Vec<UsageSnapshot> = self.usage
+            .iter()
+            .map(|entry| {
+                let ((scope, value), usage) = entry.pair();
+                UsageSnapshot {
+                    scope: scope.clone(),
+                    value: value.clone(),
+                    window_count: usage.window_count,
+                    day_count: usage.day_count,
+                    total_count: usage.total_count,
+                    exhausted_for: usage.exhausted_until
+                        .filter(|until| *until > now)
+                        .map(|until| until - now),
+                    last_status: usage.last_status,
+                }
+            })
+            .collect();
+
+        snapshot.sort_by(|a, b| (&a.scope, a.value.as_str()).cmp(&(&b.scope, b.value.as_str())));
+        snapshot
+    }
+}
+
+fn current_day() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() / 86_400
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(rate_limit: Option<u32>, daily_quota: Option<u64>) -> QuotaConfig {
+        QuotaConfig {
+            rate_limit,
+            rate_window: 60,
+            daily_quota,
+            cooldown: 60,
+            exhaust_on: vec![401, 429],
+        }
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let tracker = QuotaTracker::new();
+        let (k1, k2) = (ContentValue::from("k1"), ContentValue::from("k2"));
+        let quota = quota(Some(2), None);
+
+        assert!(tracker.try_acquire("r:h", &k1, Some(&quota)));
+        assert!(tracker.try_acquire("r:h", &k1, Some(&quota)));
+        assert!(!tracker.is_available("r:h", &k1, Some(&quota)));
+        assert!(!tracker.try_acquire("r:h", &k1, Some(&quota)));
+        assert!(tracker.is_available("r:h", &k2, Some(&quota)));
+        assert!(tracker.is_available("other", &k1, Some(&quota)));
+    }
+
+    #[test]
+    fn test_daily_quota() {
+        let tracker = QuotaTracker::new();
+        let k1 = ContentValue::from("k1");
+        let quota = quota(None, Some(1));
+
+        assert!(tracker.try_acquire("r:h", &k1, Some(&quota)));
+        assert!(!tracker.try_acquire("r:h", &k1, Some(&quota)));
+    }
+
+    #[test]
+    fn test_exhausted_on_status() {
+        let tracker = QuotaTracker::new();
+        let k1 = ContentValue::from("k1");
+        let quota = quota(None, None);
+
+        assert!(tracker.try_acquire("r:h", &k1, Some(&quota)));
+        assert!(!tracker.record_response("r:h", &k1, &quota, 200, None));
+        assert!(tracker.is_available("r:h", &k1, Some(&quota)));
+
+        assert!(tracker.record_response("r:h", &k1, &quota, 429, Some(Duration::from_secs(30))));
+        assert!(!tracker.is_available("r:h", &k1, Some(&quota)));
+
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].last_status, Some(429));
+        assert!(snapshot[0].exhausted_for.unwrap() <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let tracker = QuotaTracker::new();
+        let (k1, k2, k3) = (ContentValue::from("k1"), ContentValue::from("k2"), ContentValue::from("k3"));
+        let quota = quota(Some(10), None);
+
+        tracker.try_acquire("r:h", &k1, Some(&quota));
+        tracker.try_acquire("r:h", &k2, Some(&quota));
+        tracker.try_acquire("removed:h", &k1, Some(&quota));
+        // Only a status was recorded, so nothing is counted against k3.
+        tracker.record_response("r:h", &k3, &quota, 200, None);
+
+        let scopes = HashMap::from([(
+            "r:h".to_string(),
+            LiveScope { quota: quota.clone(), values: Some(ContentSet::new(vec!["k1".into(), "k3".into()])) },
+        )]);
+        assert_eq!(tracker.cleanup(&scopes), 3);
+
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!((snapshot[0].scope.as_str(), snapshot[0].value.as_str()), ("r:h", "k1"));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::{RateLimitConfig, RateLimitKey};
+use axum::body::Body;
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::Response;
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Token buckets for rule rate limits, keyed by rule name and client key.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: DashMap<(String, String), Bucket>,
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    capacity: f64,
+    /// Tokens added per second.
+    rate: f64,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Time until the bucket is full again, at which point it can be dropped.
+    fn time_to_full(&self) -> Duration {
+        Duration::from_secs_f64((self.capacity - self.tokens).max(0.0) / self.rate)
+    }
+}
+
+/// Outcome of a rate limit check, with the values of the `RateLimit-*` headers.
+#[derive(Debug, Clone)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Time until the bucket is full again.
+    pub reset: Duration,
+    /// Time until the next token, for rejected requests.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimitDecision {
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        headers.insert("ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("ratelimit-reset", HeaderValue::from(ceil_secs(self.reset)));
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(RETRY_AFTER, HeaderValue::from(ceil_secs(retry_after).max(1)));
+        }
+    }
+
+    /// The `429 Too Many Requests` response for a rejected request.
+    pub fn rejection(&self) -> Response {
+        let mut response = Response::new(Body::from("Too Many Requests"));
+        *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+        self.apply_headers(response.headers_mut());
+        response
+    }
+}
+
+/// A point-in-time view of one bucket.
+#[derive(Debug, Clone)]
+pub struct BucketSnapshot {
+    pub rule: String,
+    pub key: String,
+    pub tokens: f64,
+    pub capacity: u32,
+    pub full_in: Duration,
+}
+
+/// The bucket key for a request: `ip:<addr>`, `header:<value>` or `global`.
+pub fn client_key(config: &RateLimitConfig, headers: &HeaderMap, client_ip: Option<IpAddr>) -> String {
+    let ip_key = || format!("ip:{}", client_ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string()));
+    match &config.key {
+        RateLimitKey::Ip => ip_key(),
+        RateLimitKey::Header { name } => headers.get(name.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(|value| format!("header:{}", value))
+            .unwrap_or_else(ip_key),
+        RateLimitKey::Global => "global".to_string(),
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a token from the bucket of `key`, if it has one.
+    pub fn check(&self, rule: &str, config: &RateLimitConfig, key: &str) -> RateLimitDecision {
+        let now = Instant::now();
+        let capacity = config.capacity() as f64;
+        let rate = config.requests as f64 / config.period as f64;
+
+        let mut bucket = self.buckets
+            .entry((rule.to_string(), key.to_string()))
+            .or_insert_with(|| Bucket { tokens: capacity, updated: now, capacity, rate });
+        // Pick up configuration changes.
+        bucket.capacity = capacity;
+        bucket.rate = rate;
+        bucket.refill(now);
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        RateLimitDecision {
+            allowed,
+            limit: config.capacity(),
+            remaining: bucket.tokens.floor() as u32,
+            reset: bucket.time_to_full(),
+            retry_after: (!allowed).then(|| Duration::from_secs_f64((1.0 - bucket.tokens) / rate)),
+        }
+    }
+
+    /// Drops buckets that have refilled completely, since a new bucket
+    /// starts out full anyway. Returns how many were dropped.
+    pub fn cleanup(&self) -> usize {
+        let now = Instant::now();
+        let before = self.buckets.len();
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(now);
+            bucket.tokens < bucket.capacity
+        });
+        before - self.buckets.len()
+    }
+
+    pub fn snapshot(&self) -> Vec<BucketSnapshot> {
+        let now = Instant::now();
+        let mut snapshot: Vec<BucketSnapshot> = self.buckets
+            .iter()
+            .map(|entry| {
+                let ((rule, key), bucket) = entry.pair();
+                let mut bucket = bucket.clone();
+                bucket.refill(now);
+                BucketSnapshot {
+                    rule: rule.clone(),
+                    key: key.clone(),
+                    tokens: bucket.tokens,
+                    capacity: bucket.capacity as u32,
+                    full_in: bucket.time_to_full(),
+                }
+            })
+            .collect();
+
+        snapshot.sort_by(|a, b| (&a.rule, &a.key).cmp(&(&b.rule, &b.key)));
+        snapshot
+    }
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(requests: u32, period: u64, burst: Option<u32>, key: RateLimitKey) -> RateLimitConfig {
+        RateLimitConfig { requests, period, burst, key }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new();
+        let config = config(1, 60, Some(2), RateLimitKey::Ip);
+
+        assert!(limiter.check("r", &config, "ip:10.0.0.1").allowed);
+        let decision = limiter.check("r", &config, "ip:10.0.0.1");
+        assert!(decision.allowed);
+        assert_eq!((decision.limit, decision.remaining), (2, 0));
+
+        let rejected = limiter.check("r", &config, "ip:10.0.0.1");
+        assert!(!rejected.allowed);
+        assert!(rejected.retry_after.unwrap() <= Duration::from_secs(60));
+        assert!(limiter.check("r", &config, "ip:10.0.0.2").allowed);
+        assert!(limiter.check("other", &config, "ip:10.0.0.1").allowed);
+
+        let response = rejected.rejection();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+        assert!(response.headers().contains_key(RETRY_AFTER));
+
+        let snapshot = limiter.snapshot();
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(limiter.cleanup(), 0);
+    }
+
+    #[test]
+    fn test_refill_and_cleanup() {
+        let limiter = RateLimiter::new();
+        let config = config(1000, 1, Some(1), RateLimitKey::Global);
+
+        assert!(limiter.check("r", &config, "global").allowed);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(limiter.check("r", &config, "global").allowed);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(limiter.cleanup(), 1);
+    }
+
+    #[test]
+    fn test_client_key() {
+        let ip = Some("10.0.0.1".parse().unwrap());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("k1"));
+        let by_header = config(1, 1, None, RateLimitKey::Header { name: "X-Api-Key".to_string() });
+
+        assert_eq!(client_key(&config(1, 1, None, RateLimitKey::Ip), &headers, ip), "ip:10.0.0.1");
+        assert_eq!(client_key(&by_header, &headers, ip), "header:k1");
+        assert_eq!(client_key(&by_header, &HeaderMap::new(), ip), "ip:10.0.0.1");
+        assert_eq!(client_key(&config(1, 1, None, RateLimitKey::Global), &headers, ip), "global");
+    }
+}
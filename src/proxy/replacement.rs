@@ -0,0 +1,437 @@
+use crate::config::ReplacementMode;
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use regex::bytes::{NoExpand, Regex as BytesRegex};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use tracing::debug;
+
+/// A body replacement pattern, compiled for its mode when the rule is loaded.
+#[derive(Debug)]
+pub struct Pattern {
+    text: String,
+    matcher: Matcher,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Literal,
+    Regex(BytesRegex),
+    JsonPointer,
+    JsonPath(JsonPath),
+    FormField,
+    XPath(XPathSelector),
+}
+
+/// Checks that a body replacement pattern is well-formed for its mode.
+pub fn validate_pattern(pattern: &str, mode: ReplacementMode) -> anyhow::Result<()> {
+    Pattern::compile(pattern, mode).map(|_| ())
+}
+
+impl Pattern {
+    pub fn compile(pattern: &str, mode: ReplacementMode) -> anyhow::Result<Self> {
+        let matcher = match mode {
+            ReplacementMode::Literal => {
+                if pattern.is_empty() {
+                    return Err(anyhow::anyhow!("pattern must not be empty"));
+                }
+                Matcher::Literal
+            }
+            ReplacementMode::Regex => {
+                Matcher::Regex(BytesRegex::new(pattern).map_err(|e| anyhow::anyhow!("invalid regex: {}", e))?)
+            }
+            ReplacementMode::JsonPath if pattern.starts_with('/') => Matcher::JsonPointer,
+            ReplacementMode::JsonPath => {
+                Matcher::JsonPath(JsonPath::parse(pattern).map_err(|e| anyhow::anyhow!("invalid JSONPath: {}", e))?)
+            }
+            ReplacementMode::FormField => {
+                if pattern.is_empty() {
+                    return Err(anyhow::anyhow!("form field name must not be empty"));
+                }
+                Matcher::FormField
+            }
+            ReplacementMode::XPath => Matcher::XPath(XPathSelector::parse(pattern)?),
+        };
+
+        Ok(Self {
+            text: pattern.to_string(),
+            matcher,
+        })
+    }
+
+    /// Applies the replacement to `body`, returning the rewritten body.
+    ///
+    /// Literal and regex modes work byte-wise, so non-UTF-8 bodies pass through
+    /// intact. Structured modes leave the body untouched when it cannot be parsed
+    /// as the expected format, so a rule can safely target mixed traffic.
+    pub fn apply(&self, body: &[u8], value: &str) -> anyhow::Result<Vec<u8>> {
+        match &self.matcher {
+            Matcher::Literal => Ok(replace_bytes(body, self.text.as_bytes(), value.as_bytes())),
+            Matcher::Regex(regex) => Ok(regex.replace_all(body, NoExpand(value.as_bytes())).into_owned()),
+            matcher => {
+                let text = match std::str::from_utf8(body) {
+                    Ok(text) => text,
+                    Err(_) => {
+                        debug!("Skipping replacement '{}': body is not UTF-8", self.text);
+                        return Ok(body.to_vec());
+                    }
+                };
+
+                let replaced = match matcher {
+                    Matcher::JsonPointer | Matcher::JsonPath(_) => self.replace_json(text, value)?,
+                    Matcher::FormField => replace_form_field(text, &self.text, value),
+                    Matcher::XPath(selector) => replace_xml(text, &self.text, selector, value)?,
+                    Matcher::Literal | Matcher::Regex(_) => unreachable!("handled above"),
+                };
+                Ok(replaced.into_bytes())
+            }
+        }
+    }
+
+    /// Like `apply`, but in regex mode `$1`/`${name}` in `replacement` expand
+    /// to the captures of each match (and `$$` to `$`). Only for replacements
+    /// rendered from a rule's own template, never for raw source values.
+    pub fn apply_expanding(&self, body: &[u8], replacement: &str) -> anyhow::Result<Vec<u8>> {
+        match &self.matcher {
+            Matcher::Regex(regex) => Ok(regex.replace_all(body, replacement.as_bytes()).into_owned()),
+            _ => self.apply(body, replacement),
+        }
+    }
+
+    fn replace_json(&self, body: &str, value: &str) -> anyhow::Result<String> {
+        let mut json: Value = match serde_json::from_str(body) {
+            Ok(json) => json,
+            Err(e) => {
+                debug!("Skipping JSONPath replacement '{}': body is not JSON: {}", self.text, e);
+                return Ok(body.to_string());
+            }
+        };
+
+        let pointers: Vec<String> = match &self.matcher {
+            Matcher::JsonPath(path) => path.query_located(&json)
+                .locations()
+                .map(|location| location.to_json_pointer())
+                .collect(),
+            _ => vec![self.text.clone()],
+        };
+
+        let mut replaced = false;
+        for pointer in pointers {
+            if let Some(target) = json.pointer_mut(&pointer) {
+                *target = json_value(target, value);
+                replaced = true;
+            }
+        }
+
+        if !replaced {
+            return Ok(body.to_string());
+        }
+
+        Ok(serde_json::to_string(&json)?)
+    }
+}
+
+/// The JSON value written over `current`. Numbers, booleans and nulls are
+/// replaced by the same kind of value when `value` parses as one; everything
+/// else becomes a string.
+fn json_value(current: &Value, value: &str) -> Value {
+    if !current.is_string() {
+        if let Ok(parsed @ (Value::Number(_) | Value::Bool(_) | Value::Null)) = serde_json::from_str(value) {
+            return parsed;
+        }
+    }
+    Value::String(value.to_string())
+}
+
+fn replace_bytes(body: &[u8], pattern: &[u8], value: &[u8]) -> Vec<u8> {
+    if pattern.is_empty() {
+        return body.to_vec();
+    }
+
+    let mut result = Vec::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(pos) = rest.windows(pattern.len()).position(|window| window == pattern) {
+        result.extend_from_slice(&rest[..pos]);
+        result.extend_from_slice(value);
+        rest = &rest[pos + pattern.len()..];
+    }
+    result.extend_from_slice(rest);
+    result
+}
+
+fn replace_form_field(body: &str, field: &str, value: &str) -> String {
+    let encoded_value: String = form_urlencoded::byte_serialize(value.as_bytes()).collect();
+
+    body.split('&')
+        .map(|pair| {
+            let raw_key = pair.split('=').next().unwrap_or(pair);
+            let matches = form_urlencoded::parse(raw_key.as_bytes())
+                .next()
+                .map(|(key, _)| key == field)
+                .unwrap_or(false);
+
+            if matches {
+                format!("{}={}", raw_key, encoded_value)
+            } else {
+                pair.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// The supported XPath subset: an absolute (`/a/b`) or descendant (`//b`)
+/// element path, where `*` matches any element and a trailing `@attr` step
+/// selects an attribute instead of the element text.
+#[derive(Debug)]
+struct XPathSelector {
+    steps: Vec<String>,
+    descendant: bool,
+    attribute: Option<String>,
+}
+
+impl XPathSelector {
+    fn parse(pattern: &str) -> anyhow::Result<Self> {
+        let (descendant, rest) = if let Some(rest) = pattern.strip_prefix("//") {
+            (true, rest)
+        } else if let Some(rest) = pattern.strip_prefix('/') {
+            (false, rest)
+        } else {
+            return Err(anyhow::anyhow!("XPath must start with '/' or '//'"));
+        };
+
+        let mut steps: Vec<String> = rest.split('/').map(str::to_string).collect();
+        let attribute = match steps.last() {
+            Some(last) if last.starts_with('@') => {
+                let name = last[1..].to_string();
+                steps.pop();
+                Some(name)
+            }
+            _ => None,
+        };
+
+        if steps.is_empty() || steps.iter().any(|s| s.is_empty() || s.contains(['[', ']', '(', ')'])) {
+            return Err(anyhow::anyhow!("unsupported XPath expression '{}'", pattern));
+        }
+
+        Ok(Self {
+            steps,
+            descendant,
+            attribute,
+        })
+    }
+
+    fn matches(&self, stack: &[String]) -> bool {
+        if stack.len() < self.steps.len() || (!self.descendant && stack.len() != self.steps.len()) {
+            return false;
+        }
+
+        let tail = &stack[stack.len() - self.steps.len()..];
+        tail.iter()
+            .zip(&self.steps)
+            .all(|(name, step)| step == "*" || name == step)
+    }
+}
+
+fn replace_xml(body: &str, pattern: &str, selector: &XPathSelector, value: &str) -> anyhow::Result<String> {
+    let mut reader = Reader::from_str(body);
+    let mut writer = Writer::new(Vec::with_capacity(body.len()));
+    let mut stack: Vec<String> = Vec::new();
+    // Depth of the element whose text is being replaced; its children are dropped.
+    let mut replacing: Option<usize> = None;
+
+    loop {
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(e) => {
+                debug!("Skipping XPath replacement '{}': body is not XML: {}", pattern, e);
+                return Ok(body.to_string());
+            }
+        };
+
+        match event {
+            Event::Eof => break,
+            Event::Start(start) => {
+                stack.push(String::from_utf8_lossy(start.name().as_ref()).into_owned());
+                if replacing.is_some() {
+                    continue;
+                }
+
+                if selector.matches(&stack) {
+                    match &selector.attribute {
+                        Some(attribute) => {
+                            writer.write_event(Event::Start(with_attribute(&start, attribute, value)?))?;
+                        }
+                        None => {
+                            writer.write_event(Event::Start(start))?;
+                            writer.write_event(Event::Text(BytesText::new(value)))?;
+                            replacing = Some(stack.len());
+                        }
+                    }
+                } else {
+                    writer.write_event(Event::Start(start))?;
+                }
+            }
+            Event::Empty(start) => {
+                stack.push(String::from_utf8_lossy(start.name().as_ref()).into_owned());
+                if replacing.is_none() {
+                    if selector.matches(&stack) {
+                        match &selector.attribute {
+                            Some(attribute) => {
+                                writer.write_event(Event::Empty(with_attribute(&start, attribute, value)?))?;
+                            }
+                            None => {
+                                let name = stack.last().cloned().unwrap_or_default();
+                                writer.write_event(Event::Start(start))?;
+                                writer.write_event(Event::Text(BytesText::new(value)))?;
+                                writer.write_event(Event::End(BytesEnd::new(name)))?;
+                            }
+                        }
+                    } else {
+                        writer.write_event(Event::Empty(start))?;
+                    }
+                }
+                stack.pop();
+            }
+            Event::End(end) => {
+                match replacing {
+                    Some(depth) if depth == stack.len() => {
+                        replacing = None;
+                        writer.write_event(Event::End(end))?;
+                    }
+                    Some(_) => {}
+                    None => writer.write_event(Event::End(end))?,
+                }
+                stack.pop();
+            }
+            other => {
+                if replacing.is_none() {
+                    writer.write_event(other)?;
+                }
+            }
+        }
+    }
+
+    Ok(String::from_utf8(writer.into_inner())?)
+}
+
+fn with_attribute<'a>(start: &BytesStart<'a>, attribute: &str, value: &str) -> anyhow::Result<BytesStart<'static>> {
+    let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+    let mut rewritten = BytesStart::new(name);
+
+    for attr in start.attributes() {
+        let attr = attr?;
+        if attr.key.as_ref() == attribute.as_bytes() {
+            rewritten.push_attribute((attribute, value));
+        } else {
+            rewritten.push_attribute(attr);
+        }
+    }
+
+    Ok(rewritten)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply_body_replacement(body: &[u8], pattern: &str, mode: ReplacementMode, value: &str) -> anyhow::Result<Vec<u8>> {
+        Pattern::compile(pattern, mode)?.apply(body, value)
+    }
+
+    #[test]
+    fn test_literal_replacement() {
+        let result = apply_body_replacement(b"key={{K}}&b={{K}}", "{{K}}", ReplacementMode::Literal, "abc").unwrap();
+        assert_eq!(result, b"key=abc&b=abc");
+    }
+
+    #[test]
+    fn test_regex_replacement_with_captures() {
+        let result = Pattern::compile(r"token=(\w+)-(\d+)", ReplacementMode::Regex).unwrap()
+            .apply_expanding(b"token=old-123", "token=new-$2")
+            .unwrap();
+        assert_eq!(result, b"token=new-123");
+    }
+
+    #[test]
+    fn test_regex_replacement_inserts_values_verbatim() {
+        let result = apply_body_replacement(b"token=old-123", r"old-(\d+)", ReplacementMode::Regex, "pa$$w0rd$1").unwrap();
+        assert_eq!(result, b"token=pa$$w0rd$1");
+    }
+
+    #[test]
+    fn test_json_path_replacement_targets_single_field() {
+        let body = r#"{"auth":{"api_key":"old"},"note":"old"}"#;
+        let result = apply_body_replacement(body.as_bytes(), "$.auth.api_key", ReplacementMode::JsonPath, "new").unwrap();
+        assert_eq!(result, r#"{"auth":{"api_key":"new"},"note":"old"}"#.as_bytes());
+
+        let result = apply_body_replacement(body.as_bytes(), "/auth/api_key", ReplacementMode::JsonPath, "new").unwrap();
+        assert_eq!(result, r#"{"auth":{"api_key":"new"},"note":"old"}"#.as_bytes());
+    }
+
+    #[test]
+    fn test_json_path_keeps_value_types() {
+        let body = r#"{"limit":10,"debug":false,"name":"a","id":null}"#;
+        let replace = |pattern: &str, value: &str| {
+            String::from_utf8(apply_body_replacement(body.as_bytes(), pattern, ReplacementMode::JsonPath, value).unwrap()).unwrap()
+        };
+
+        assert_eq!(replace("$.limit", "25"), r#"{"limit":25,"debug":false,"name":"a","id":null}"#);
+        assert_eq!(replace("$.debug", "true"), r#"{"limit":10,"debug":true,"name":"a","id":null}"#);
+        assert_eq!(replace("$.name", "42"), r#"{"limit":10,"debug":false,"name":"42","id":null}"#);
+        assert_eq!(replace("$.id", "7"), r#"{"limit":10,"debug":false,"name":"a","id":7}"#);
+        assert_eq!(replace("$.limit", "many"), r#"{"limit":"many","debug":false,"name":"a","id":null}"#);
+    }
+
+    #[test]
+    fn test_json_path_ignores_non_json_body() {
+        let result = apply_body_replacement(b"not json", "$.a", ReplacementMode::JsonPath, "x").unwrap();
+        assert_eq!(result, b"not json");
+    }
+
+    #[test]
+    fn test_form_field_replacement() {
+        let result = apply_body_replacement(
+            b"user=bob&api_key=old&note=api_key+old",
+            "api_key",
+            ReplacementMode::FormField,
+            "a b&c",
+        ).unwrap();
+        assert_eq!(result, b"user=bob&api_key=a+b%26c&note=api_key+old");
+    }
+
+    #[test]
+    fn test_xpath_element_replacement() {
+        let body = "<req><auth><key>old</key></auth><key>old</key></req>";
+        let result = apply_body_replacement(body.as_bytes(), "/req/auth/key", ReplacementMode::XPath, "n&w").unwrap();
+        assert_eq!(result, "<req><auth><key>n&amp;w</key></auth><key>old</key></req>".as_bytes());
+
+        let result = apply_body_replacement(body.as_bytes(), "//key", ReplacementMode::XPath, "new").unwrap();
+        assert_eq!(result, "<req><auth><key>new</key></auth><key>new</key></req>".as_bytes());
+    }
+
+    #[test]
+    fn test_xpath_attribute_replacement() {
+        let body = r#"<req><auth key="old" user="bob"/></req>"#;
+        let result = apply_body_replacement(body.as_bytes(), "/req/auth/@key", ReplacementMode::XPath, "new").unwrap();
+        assert_eq!(result, r#"<req><auth key="new" user="bob"/></req>"#.as_bytes());
+    }
+
+    #[test]
+    fn test_binary_body_is_preserved() {
+        let body = [0xff, 0x00, b'K', b'E', b'Y', 0xfe];
+        let result = apply_body_replacement(&body, "KEY", ReplacementMode::Literal, "abc").unwrap();
+        assert_eq!(result, [0xff, 0x00, b'a', b'b', b'c', 0xfe]);
+
+        let result = apply_body_replacement(&body, "$.a", ReplacementMode::JsonPath, "x").unwrap();
+        assert_eq!(result, body);
+    }
+
+    #[test]
+    fn test_validate_pattern() {
+        assert!(validate_pattern("(unclosed", ReplacementMode::Regex).is_err());
+        assert!(validate_pattern("$.a[", ReplacementMode::JsonPath).is_err());
+        assert!(validate_pattern("a/b", ReplacementMode::XPath).is_err());
+        assert!(validate_pattern("//a/@b", ReplacementMode::XPath).is_ok());
+    }
+}
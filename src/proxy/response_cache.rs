@@ -0,0 +1,832 @@
+use crate::config::{ResponseCacheBackend, ResponseCacheConfig};
+use crate::content::secret::digest;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header::{
+    AGE, AUTHORIZATION, CACHE_CONTROL, CONNECTION, DATE, ETAG, EXPIRES, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED, SET_COOKIE, VARY,
+};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method};
+use axum::response::Response;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+/// Response header reporting whether the response came from the cache:
+/// `HIT`, `REVALIDATED` (stale, confirmed by a 304) or `MISS`.
+pub const X_CACHE: &str = "x-cache";
+
+/// Statuses that may be stored (RFC 9111 heuristically cacheable set).
+const CACHEABLE_STATUSES: &[u16] = &[200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// Headers a `304 Not Modified` must not overwrite in the stored response:
+/// those describing the stored body's framing and encoding, and hop-by-hop
+/// headers (RFC 9111 §3.2, §4.3.4).
+const NOT_UPDATED_ON_REFRESH: &[&str] = &[
+    "content-length", "content-encoding", "content-range", "transfer-encoding",
+    "connection", "keep-alive", "te", "trailer", "upgrade",
+];
+
+/// A stored upstream response, one variant of a URL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    /// Path and query of the request.
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    #[serde(with = "base64_body")]
+    pub body: Vec<u8>,
+    /// Request header values named by the response's `Vary`, keyed by lowercase name.
+    pub vary: Vec<(String, Option<String>)>,
+    /// Unix time the upstream generated the response (storage time minus `Age`).
+    pub date: u64,
+    /// Unix time after which the response must be revalidated.
+    pub fresh_until: u64,
+    /// `Cache-Control: no-cache`: always revalidate before use.
+    pub no_cache: bool,
+}
+
+pub enum CacheLookup {
+    Fresh(CachedResponse),
+    Stale(CachedResponse),
+    Miss,
+}
+
+impl CachedResponse {
+    pub fn is_fresh(&self) -> bool {
+        !self.no_cache && now() < self.fresh_until
+    }
+
+    pub fn etag(&self) -> Option<&str> {
+        self.header(ETAG.as_str())
+    }
+
+    pub fn last_modified(&self) -> Option<&str> {
+        self.header(LAST_MODIFIED.as_str())
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether this variant was stored for a request with the same `Vary` header values.
+    fn matches(&self, request_headers: &HeaderMap) -> bool {
+        self.vary.iter().all(|(name, value)| header_string(request_headers, name).as_deref() == value.as_deref())
+    }
+
+    /// Builds a response from the cached one, with `Age` and `X-Cache` set.
+    pub fn to_response(&self, cache_status: &'static str) -> anyhow::Result<Response> {
+        let mut builder = Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case(AGE.as_str()) {
+                builder = builder.header(name.as_str(), value.as_str());
+            }
+        }
+        builder = builder
+            .header(AGE, now().saturating_sub(self.date).to_string())
+            .header(X_CACHE, cache_status);
+        Ok(builder.body(Body::from(self.body.clone()))?)
+    }
+
+    /// Applies the headers of a `304 Not Modified` and recomputes freshness.
+    pub fn refresh(&mut self, not_modified: &HeaderMap, config: &ResponseCacheConfig) {
+        // Headers listed in `Connection` are hop-by-hop too.
+        let connection: Vec<String> = not_modified.get_all(CONNECTION).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect();
+
+        for name in not_modified.keys() {
+            let name_str = name.as_str();
+            if NOT_UPDATED_ON_REFRESH.contains(&name_str)
+                || name_str.starts_with("proxy-")
+                || connection.iter().any(|listed| listed == name_str)
+            {
+                continue;
+            }
+            let values: Vec<String> = not_modified.get_all(name).iter()
+                .filter_map(|value| value.to_str().ok().map(str::to_string))
+                .collect();
+            if values.is_empty() {
+                continue;
+            }
+            self.headers.retain(|(header, _)| !header.eq_ignore_ascii_case(name.as_str()));
+            self.headers.extend(values.into_iter().map(|value| (name.to_string(), value)));
+        }
+
+        let headers = self.header_map();
+        let directives = cache_directives(&headers);
+        let lifetime = freshness_lifetime(&headers, &directives, config).unwrap_or(0);
+        self.date = response_date(&headers);
+        self.fresh_until = self.date + lifetime;
+        self.no_cache = directives.contains_key("no-cache");
+    }
+
+    /// Unix time after which the response can neither be served nor
+    /// revalidated, or `None` if it has a validator.
+    fn expires_at(&self) -> Option<u64> {
+        if self.etag().is_some() || self.last_modified().is_some() {
+            return None;
+        }
+        Some(if self.no_cache { 0 } else { self.fresh_until })
+    }
+
+    /// Approximate memory held by the response.
+    fn size(&self) -> usize {
+        self.url.len()
+            + self.body.len()
+            + self.headers.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>()
+    }
+
+    fn header_map(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                headers.append(name, value);
+            }
+        }
+        headers
+    }
+}
+
+/// Cache key for a request, or `None` if it must not use the cache.
+pub fn request_key(request: &Request) -> Option<String> {
+    if request.method() != Method::GET || cache_directives(request.headers()).contains_key("no-store") {
+        return None;
+    }
+    request.uri().path_and_query().map(|pq| pq.as_str().to_string())
+}
+
+/// Whether the client asked for a cached response to be revalidated.
+pub fn request_requires_revalidation(headers: &HeaderMap) -> bool {
+    let directives = cache_directives(headers);
+    directives.contains_key("no-cache")
+        || directives.get("max-age").and_then(|v| v.as_deref()).is_some_and(|v| v == "0")
+}
+
+/// Whether the client sent its own conditional headers, in which case a 304
+/// from upstream belongs to the client rather than to the cache.
+pub fn is_conditional(headers: &HeaderMap) -> bool {
+    headers.contains_key(IF_NONE_MATCH) || headers.contains_key(IF_MODIFIED_SINCE)
+}
+
+/// Decides whether a response may be stored and, if so, builds the entry.
+pub fn build_entry(
+    url: &str,
+    request_headers: &HeaderMap,
+    status: u16,
+    response_headers: &HeaderMap,
+    body: &[u8],
+    config: &ResponseCacheConfig,
+) -> Option<CachedResponse> {
+    if !CACHEABLE_STATUSES.contains(&status) || body.len() > config.max_body_size {
+        return None;
+    }
+
+    let directives = cache_directives(response_headers);
+    if directives.contains_key("no-store") || directives.contains_key("private") {
+        return None;
+    }
+    if response_headers.contains_key(SET_COOKIE) {
+        return None;
+    }
+    if request_headers.contains_key(AUTHORIZATION)
+        && !directives.contains_key("public")
+        && !directives.contains_key("s-maxage")
+    {
+        return None;
+    }
+
+    let vary_names: Vec<String> = response_headers.get_all(VARY).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if vary_names.iter().any(|name| name == "*") {
+        return None;
+    }
+
+    let has_validator = response_headers.contains_key(ETAG) || response_headers.contains_key(LAST_MODIFIED);
+    let lifetime = match freshness_lifetime(response_headers, &directives, config) {
+        Some(lifetime) => lifetime,
+        None if has_validator => 0,
+        None => return None,
+    };
+    if lifetime == 0 && !has_validator {
+        return None;
+    }
+
+    let date = response_date(response_headers);
+    Some(CachedResponse {
+        url: url.to_string(),
+        status,
+        headers: response_headers.iter()
+            .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.to_string(), value.to_string())))
+            .collect(),
+        body: body.to_vec(),
+        vary: vary_names.into_iter()
+            .map(|name| {
+                let value = header_string(request_headers, &name);
+                (name, value)
+            })
+            .collect(),
+        date,
+        fresh_until: date + lifetime,
+        no_cache: directives.contains_key("no-cache"),
+    })
+}
+
+/// Seconds the response stays fresh: `s-maxage`, then `max-age`, then
+/// `Expires` relative to `Date`, then the rule's `default_ttl`.
+fn freshness_lifetime(
+    headers: &HeaderMap,
+    directives: &HashMap<String, Option<String>>,
+    config: &ResponseCacheConfig,
+) -> Option<u64> {
+    for directive in ["s-maxage", "max-age"] {
+        if let Some(Some(value)) = directives.get(directive) {
+            return Some(value.parse().unwrap_or(0));
+        }
+    }
+
+    if let Some(expires) = headers.get(EXPIRES) {
+        // An invalid Expires, such as "0", means already expired.
+        let expires = expires.to_str().ok().and_then(|v| httpdate::parse_http_date(v).ok());
+        let date = headers.get(DATE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok())
+            .unwrap_or_else(SystemTime::now);
+        return Some(expires
+            .and_then(|expires| expires.duration_since(date).ok())
+            .map_or(0, |lifetime| lifetime.as_secs()));
+    }
+
+    (config.default_ttl > 0).then_some(config.default_ttl)
+}
+
+/// When the upstream generated the response, from its `Age` header.
+fn response_date(headers: &HeaderMap) -> u64 {
+    let age = header_string(headers, AGE.as_str())
+        .and_then(|age| age.parse::<u64>().ok())
+        .unwrap_or(0);
+    now().saturating_sub(age)
+}
+
+fn cache_directives(headers: &HeaderMap) -> HashMap<String, Option<String>> {
+    headers.get_all(CACHE_CONTROL).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|directive| {
+            let mut parts = directive.splitn(2, '=');
+            let name = parts.next()?.trim().to_ascii_lowercase();
+            let value = parts.next().map(|v| v.trim().trim_matches('"').to_string());
+            (!name.is_empty()).then_some((name, value))
+        })
+        .collect()
+}
+
+fn header_string(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<&str> = headers.get_all(name).iter().filter_map(|v| v.to_str().ok()).collect();
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Upstream response cache. Memory-backed rules keep their responses in a
+/// per-rule index; disk-backed rules keep one JSON file per URL, with the
+/// index holding only their sizes. Either way, the least recently used URLs
+/// are evicted to stay within `max_entries` and `max_bytes`.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    stores: DashMap<StoreKey, Arc<RuleStore>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum StoreKey {
+    Memory(String),
+    Disk(PathBuf),
+}
+
+#[derive(Debug, Default)]
+struct RuleStore {
+    index: Mutex<RuleIndex>,
+    /// Set once the disk directory has been scanned into the index.
+    loaded: OnceCell<()>,
+}
+
+/// One rule's cached URLs, by URL and by recency.
+#[derive(Debug, Default)]
+struct RuleIndex {
+    entries: HashMap<String, IndexEntry>,
+    /// URLs by the clock value of their last use, least recent first.
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+    bytes: usize,
+}
+
+#[derive(Debug)]
+struct IndexEntry {
+    last_used: u64,
+    size: usize,
+    /// Unix time after which no variant can be served or revalidated, if ever.
+    expires: Option<u64>,
+    /// The variants themselves, for the memory backend.
+    variants: Option<Vec<CachedResponse>>,
+}
+
+impl RuleIndex {
+    /// Marks `url` as used, returning its entry.
+    fn touch(&mut self, url: &str) -> Option<&IndexEntry> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(url)?;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = self.clock;
+        self.recency.insert(self.clock, url.to_string());
+        Some(entry)
+    }
+
+    /// Adds or replaces `url`, then evicts the least recently used URLs
+    /// until the limits hold. Returns the evicted URLs, which may include `url`.
+    fn insert(&mut self, url: String, entry: IndexEntry, config: &ResponseCacheConfig) -> Vec<String> {
+        self.remove(&url);
+        self.clock += 1;
+        self.bytes += entry.size;
+        self.recency.insert(self.clock, url.clone());
+        self.entries.insert(url, IndexEntry { last_used: self.clock, ..entry });
+
+        let mut evicted = Vec::new();
+        while self.entries.len() > config.max_entries || self.bytes > config.max_bytes {
+            let Some((_, url)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&url) {
+                self.bytes -= entry.size;
+            }
+            evicted.push(url);
+        }
+        evicted
+    }
+
+    fn remove(&mut self, url: &str) -> Option<IndexEntry> {
+        let entry = self.entries.remove(url)?;
+        self.recency.remove(&entry.last_used);
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn remove_where(&mut self, condition: impl Fn(&str, &IndexEntry) -> bool) -> Vec<String> {
+        let urls: Vec<String> = self.entries.iter()
+            .filter(|(url, entry)| condition(url, entry))
+            .map(|(url, _)| url.clone())
+            .collect();
+        for url in &urls {
+            self.remove(url);
+        }
+        urls
+    }
+}
+
+impl IndexEntry {
+    fn new(variants: &[CachedResponse], size: usize) -> Self {
+        Self {
+            last_used: 0,
+            size,
+            expires: variants.iter().map(CachedResponse::expires_at).try_fold(0, |latest, expires| Some(latest.max(expires?))),
+            variants: None,
+        }
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+impl ResponseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn lookup(
+        &self,
+        rule: &str,
+        config: &ResponseCacheConfig,
+        url: &str,
+        request_headers: &HeaderMap,
+    ) -> CacheLookup {
+        let store = self.store_for(rule, config).await;
+        let found = {
+            let mut index = lock(&store);
+            match index.touch(url) {
+                Some(entry) if entry.is_expired(now()) => {
+                    index.remove(url);
+                    None
+                }
+                Some(entry) => Some(entry.variants.clone()),
+                None => return CacheLookup::Miss,
+            }
+        };
+        let Some(variants) = found else {
+            self.delete_files(rule, config, &[url.to_string()]).await;
+            return CacheLookup::Miss;
+        };
+
+        let variants = match config.backend {
+            ResponseCacheBackend::Memory => variants,
+            ResponseCacheBackend::Disk => {
+                let variants = read_disk(&disk_path(config, rule, url)).await;
+                if variants.is_none() {
+                    lock(&store).remove(url);
+                }
+                variants
+            }
+        };
+
+        match variants.and_then(|variants| variants.into_iter().find(|v| v.matches(request_headers))) {
+            Some(cached) if cached.is_fresh() => CacheLookup::Fresh(cached),
+            Some(cached) => CacheLookup::Stale(cached),
+            None => CacheLookup::Miss,
+        }
+    }
+
+    /// Stores `entry`, replacing the variant with the same `Vary` values.
+    pub async fn store(&self, rule: &str, config: &ResponseCacheConfig, entry: CachedResponse) {
+        let store = self.store_for(rule, config).await;
+        let url = entry.url.clone();
+
+        let evicted = match config.backend {
+            ResponseCacheBackend::Memory => {
+                let mut index = lock(&store);
+                let mut variants = index.entries.get(&url)
+                    .and_then(|existing| existing.variants.clone())
+                    .unwrap_or_default();
+                variants.retain(|variant| variant.vary != entry.vary);
+                variants.push(entry);
+
+                let size = variants.iter().map(CachedResponse::size).sum();
+                let indexed = IndexEntry { variants: Some(variants.clone()), ..IndexEntry::new(&variants, size) };
+                index.insert(url, indexed, config)
+            }
+            ResponseCacheBackend::Disk => {
+                let path = disk_path(config, rule, &url);
+                let mut variants = read_disk(&path).await.unwrap_or_default();
+                variants.retain(|variant| variant.vary != entry.vary);
+                variants.push(entry);
+                match write_disk(&path, &variants).await {
+                    Ok(size) => lock(&store).insert(url, IndexEntry::new(&variants, size), config),
+                    Err(e) => {
+                        warn!("Failed to write cached response to {}: {}", path.display(), e);
+                        return;
+                    }
+                }
+            }
+        };
+
+        self.delete_files(rule, config, &evicted).await;
+    }
+
+    /// Removes a rule's cached responses whose URL starts with `url_prefix`
+    /// (all of them if `None`), returning how many URLs were removed.
+    pub async fn purge(&self, rule: &str, config: &ResponseCacheConfig, url_prefix: Option<&str>) -> anyhow::Result<usize> {
+        let matches = |url: &str| url_prefix.is_none_or(|prefix| url.starts_with(prefix));
+
+        let store = self.store_for(rule, config).await;
+        let removed = lock(&store).remove_where(|url, _| matches(url));
+        if config.backend == ResponseCacheBackend::Memory {
+            return Ok(removed.len());
+        }
+
+        let dir = disk_dir(config, rule);
+        let mut listing = match tokio::fs::read_dir(&dir).await {
+            Ok(listing) => listing,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut removed = 0;
+        while let Some(file) = listing.next_entry().await? {
+            let path = file.path();
+            let url = read_disk(&path).await
+                .and_then(|variants| variants.first().map(|v| v.url.clone()));
+            // Unreadable files are dropped as well, since they can never be served.
+            if url.as_deref().is_none_or(matches) {
+                tokio::fs::remove_file(&path).await?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Removes a rule's responses that can no longer be served or
+    /// revalidated, returning how many URLs were removed.
+    pub async fn remove_expired(&self, rule: &str, config: &ResponseCacheConfig) -> usize {
+        let store = self.store_for(rule, config).await;
+        let now = now();
+        let expired = lock(&store).remove_where(|_, entry| entry.is_expired(now));
+        self.delete_files(rule, config, &expired).await;
+        expired.len()
+    }
+
+    /// The index for the rule, reading the disk directory into it on first use.
+    async fn store_for(&self, rule: &str, config: &ResponseCacheConfig) -> Arc<RuleStore> {
+        let key = match config.backend {
+            ResponseCacheBackend::Memory => StoreKey::Memory(rule.to_string()),
+            ResponseCacheBackend::Disk => StoreKey::Disk(disk_dir(config, rule)),
+        };
+        let store = self.stores.entry(key).or_default().clone();
+
+        if config.backend == ResponseCacheBackend::Disk {
+            store.loaded.get_or_init(|| async {
+                let evicted = load_disk_index(&store, &disk_dir(config, rule), config).await;
+                self.delete_files(rule, config, &evicted).await;
+            }).await;
+        }
+        store
+    }
+
+    async fn delete_files(&self, rule: &str, config: &ResponseCacheConfig, urls: &[String]) {
+        if config.backend != ResponseCacheBackend::Disk {
+            return;
+        }
+        for url in urls {
+            let path = disk_path(config, rule, url);
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to remove cached response {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+fn lock(store: &RuleStore) -> std::sync::MutexGuard<'_, RuleIndex> {
+    store.index.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Indexes the files left in `dir` by an earlier run, deleting the ones that
+/// cannot be read. Returns the URLs evicted to fit the limits.
+async fn load_disk_index(store: &RuleStore, dir: &Path, config: &ResponseCacheConfig) -> Vec<String> {
+    let Ok(mut listing) = tokio::fs::read_dir(dir).await else {
+        return Vec::new();
+    };
+
+    let mut evicted = Vec::new();
+    while let Ok(Some(file)) = listing.next_entry().await {
+        let path = file.path();
+        let size = file.metadata().await.map_or(0, |metadata| metadata.len() as usize);
+        match read_disk(&path).await {
+            Some(variants) if !variants.is_empty() => {
+                let url = variants[0].url.clone();
+                evicted.extend(lock(store).insert(url, IndexEntry::new(&variants, size), config));
+            }
+            _ => {
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    warn!("Failed to remove unreadable cached response {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+    evicted
+}
+
+/// Directory of a rule's cached responses: the rule name made safe for a
+/// path, plus a short hash so that names sanitized alike stay apart.
+fn disk_dir(config: &ResponseCacheConfig, rule: &str) -> PathBuf {
+    let sanitized: String = rule.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    PathBuf::from(config.directory.as_deref().unwrap_or("."))
+        .join(format!("{}-{}", sanitized, &digest(rule)[..8]))
+}
+
+fn disk_path(config: &ResponseCacheConfig, rule: &str, url: &str) -> PathBuf {
+    disk_dir(config, rule).join(format!("{}.json", digest(url)))
+}
+
+async fn read_disk(path: &Path) -> Option<Vec<CachedResponse>> {
+    let data = tokio::fs::read(path).await.ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+/// Writes through a temporary file so readers never see a partial entry.
+/// Returns the size of the file.
+async fn write_disk(path: &Path, variants: &[CachedResponse]) -> anyhow::Result<usize> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let temp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+    let data = serde_json::to_vec(variants)?;
+    tokio::fs::write(&temp, &data).await?;
+    tokio::fs::rename(&temp, path).await?;
+    Ok(data.len())
+}
+
+mod base64_body {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(backend: ResponseCacheBackend, directory: Option<String>) -> ResponseCacheConfig {
+        ResponseCacheConfig {
+            backend,
+            directory,
+            default_ttl: 0,
+            max_entries: 2,
+            max_bytes: 4096,
+            max_body_size: 1024,
+        }
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(HeaderName::from_bytes(name.as_bytes()).unwrap(), HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_storability() {
+        let config = config(ResponseCacheBackend::Memory, None);
+        let request = HeaderMap::new();
+
+        let fresh = build_entry("/a", &request, 200, &headers(&[("cache-control", "max-age=60")]), b"x", &config).unwrap();
+        assert!(fresh.is_fresh());
+
+        let revalidate = build_entry("/a", &request, 200, &headers(&[("etag", "\"v1\"")]), b"x", &config).unwrap();
+        assert!(!revalidate.is_fresh());
+        assert_eq!(revalidate.etag(), Some("\"v1\""));
+
+        assert!(build_entry("/a", &request, 200, &HeaderMap::new(), b"x", &config).is_none());
+        assert!(build_entry("/a", &request, 200, &headers(&[("cache-control", "private, max-age=60")]), b"x", &config).is_none());
+        assert!(build_entry("/a", &request, 500, &headers(&[("cache-control", "max-age=60")]), b"x", &config).is_none());
+        assert!(build_entry("/a", &request, 200, &headers(&[("cache-control", "max-age=60"), ("vary", "*")]), b"x", &config).is_none());
+        assert!(build_entry("/a", &request, 200, &headers(&[("cache-control", "max-age=60")]), &[0; 2048], &config).is_none());
+
+        let authorized = headers(&[("authorization", "Bearer t")]);
+        assert!(build_entry("/a", &authorized, 200, &headers(&[("cache-control", "max-age=60")]), b"x", &config).is_none());
+        assert!(build_entry("/a", &authorized, 200, &headers(&[("cache-control", "public, max-age=60")]), b"x", &config).is_some());
+
+        let expired = headers(&[("expires", "Thu, 01 Jan 1970 00:00:00 GMT")]);
+        assert!(build_entry("/a", &request, 200, &expired, b"x", &config).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memory_lookup_with_vary_and_purge() {
+        let cache = ResponseCache::new();
+        let config = config(ResponseCacheBackend::Memory, None);
+        let response = headers(&[("cache-control", "max-age=60"), ("vary", "Accept-Language")]);
+
+        let english = headers(&[("accept-language", "en")]);
+        let german = headers(&[("accept-language", "de")]);
+        cache.store("r", &config, build_entry("/static/a", &english, 200, &response, b"hello", &config).unwrap()).await;
+
+        assert!(matches!(cache.lookup("r", &config, "/static/a", &english).await, CacheLookup::Fresh(_)));
+        assert!(matches!(cache.lookup("r", &config, "/static/a", &german).await, CacheLookup::Miss));
+        assert!(matches!(cache.lookup("other", &config, "/static/a", &english).await, CacheLookup::Miss));
+
+        cache.store("r", &config, build_entry("/other", &english, 200, &response, b"x", &config).unwrap()).await;
+        assert_eq!(cache.purge("r", &config, Some("/static/")).await.unwrap(), 1);
+        assert!(matches!(cache.lookup("r", &config, "/other", &english).await, CacheLookup::Fresh(_)));
+    }
+
+    #[tokio::test]
+    async fn test_memory_eviction_is_least_recently_used() {
+        let cache = ResponseCache::new();
+        let config = config(ResponseCacheBackend::Memory, None);
+        let request = HeaderMap::new();
+        let entry = |url: &str| build_entry(url, &request, 200, &headers(&[("cache-control", "max-age=60")]), b"x", &config).unwrap();
+
+        cache.store("r", &config, entry("/a")).await;
+        cache.store("r", &config, entry("/b")).await;
+        // Reading /a makes /b the least recently used.
+        assert!(matches!(cache.lookup("r", &config, "/a", &request).await, CacheLookup::Fresh(_)));
+        cache.store("r", &config, entry("/c")).await;
+
+        assert!(matches!(cache.lookup("r", &config, "/a", &request).await, CacheLookup::Fresh(_)));
+        assert!(matches!(cache.lookup("r", &config, "/b", &request).await, CacheLookup::Miss));
+        assert!(matches!(cache.lookup("r", &config, "/c", &request).await, CacheLookup::Fresh(_)));
+    }
+
+    #[tokio::test]
+    async fn test_disk_limits_and_expiry() {
+        let dir = std::env::temp_dir().join(format!("ultiproxy-response-cache-{}", uuid::Uuid::new_v4()));
+        let mut config = config(ResponseCacheBackend::Disk, Some(dir.to_string_lossy().into_owned()));
+        let request = HeaderMap::new();
+        let storable = config.clone();
+        let entry = |url: &str, body: &[u8]| build_entry(url, &request, 200, &headers(&[("cache-control", "max-age=60")]), body, &storable).unwrap();
+        let files = || std::fs::read_dir(disk_dir(&storable, "r")).unwrap().count();
+        let disk_bytes = || std::fs::read_dir(disk_dir(&storable, "r")).unwrap()
+            .map(|file| file.unwrap().metadata().unwrap().len() as usize)
+            .sum::<usize>();
+
+        let cache = ResponseCache::new();
+        for url in ["/a", "/b", "/c"] {
+            cache.store("r", &config, entry(url, b"x")).await;
+        }
+        assert_eq!(files(), 2);
+        assert!(matches!(cache.lookup("r", &config, "/a", &request).await, CacheLookup::Miss));
+
+        // Files left by an earlier run count towards the limits.
+        let cache = ResponseCache::new();
+        cache.store("r", &config, entry("/d", b"x")).await;
+        assert_eq!(files(), 2);
+
+        config.max_entries = 10;
+        for url in ["/l1", "/l2", "/l3"] {
+            cache.store("r", &config, entry(url, &[b'x'; 1024])).await;
+        }
+        assert!(files() < 5);
+        assert!(disk_bytes() <= config.max_bytes);
+
+        let mut expired = entry("/expired", b"x");
+        expired.fresh_until = now() - 1;
+        cache.store("r", &config, expired).await;
+        let count = files();
+        assert_eq!(cache.remove_expired("r", &config).await, 1);
+        assert_eq!(files(), count - 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disk_backend_and_revalidation() {
+        let dir = std::env::temp_dir().join(format!("ultiproxy-response-cache-{}", uuid::Uuid::new_v4()));
+        let config = config(ResponseCacheBackend::Disk, Some(dir.to_string_lossy().into_owned()));
+        let cache = ResponseCache::new();
+        let request = HeaderMap::new();
+
+        let entry = build_entry("/a", &request, 200, &headers(&[("etag", "\"v1\"")]), b"body", &config).unwrap();
+        cache.store("r", &config, entry).await;
+
+        let CacheLookup::Stale(mut cached) = cache.lookup("r", &config, "/a", &request).await else {
+            panic!("expected a stale entry");
+        };
+        assert_eq!(cached.body, b"body");
+
+        cached.refresh(&headers(&[("cache-control", "max-age=60")]), &config);
+        cache.store("r", &config, cached).await;
+        assert!(matches!(cache.lookup("r", &config, "/a", &request).await, CacheLookup::Fresh(_)));
+
+        assert_eq!(cache.purge("r", &config, None).await.unwrap(), 1);
+        assert!(matches!(cache.lookup("r", &config, "/a", &request).await, CacheLookup::Miss));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_disk_dir_keeps_similar_rule_names_apart() {
+        let config = config(ResponseCacheBackend::Disk, Some("/cache".to_string()));
+        assert_ne!(disk_dir(&config, "api/v1"), disk_dir(&config, "api_v1"));
+        assert!(disk_dir(&config, "../up").starts_with("/cache"));
+    }
+
+    #[test]
+    fn test_refresh_keeps_framing_headers() {
+        let config = config(ResponseCacheBackend::Memory, None);
+        let stored = headers(&[("etag", "\"v1\""), ("content-length", "4"), ("content-encoding", "gzip")]);
+        let mut cached = build_entry("/a", &HeaderMap::new(), 200, &stored, b"body", &config).unwrap();
+
+        cached.refresh(&headers(&[
+            ("cache-control", "max-age=60"),
+            ("content-length", "0"),
+            ("transfer-encoding", "chunked"),
+            ("connection", "close, x-hop"),
+            ("x-hop", "1"),
+            ("keep-alive", "timeout=5"),
+            ("proxy-authenticate", "Basic"),
+            ("etag", "\"v2\""),
+        ]), &config);
+
+        assert!(cached.is_fresh());
+        assert_eq!(cached.etag(), Some("\"v2\""));
+        assert_eq!(cached.header("content-length"), Some("4"));
+        assert_eq!(cached.header("content-encoding"), Some("gzip"));
+        for name in ["transfer-encoding", "connection", "x-hop", "keep-alive", "proxy-authenticate"] {
+            assert_eq!(cached.header(name), None, "{}", name);
+        }
+    }
+}
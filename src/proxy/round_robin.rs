@@ -1,5 +1,13 @@
+use crate::config::{ContentSource, QuotaConfig};
+use crate::content::{ContentSet, ContentValue};
+use crate::proxy::quota::{LiveScope, QuotaTracker, UsageSnapshot};
+use std::collections::HashMap;
+use crate::proxy::selection::ContentSelector;
+use crate::proxy::template::RequestContext;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
 
 #[derive(Debug)]
 pub struct RoundRobinSelector {
@@ -26,6 +34,7 @@ impl RoundRobinSelector {
         self.select(items).cloned()
     }
 
+    #[allow(dead_code)]
     pub fn reset(&self) {
         self.counter.store(0, Ordering::Relaxed);
     }
@@ -40,7 +49,8 @@ impl Default for RoundRobinSelector {
 #[derive(Debug, Clone)]
 pub struct RoundRobinManager {
     url_selector: Arc<RoundRobinSelector>,
-    content_selectors: Arc<dashmap::DashMap<String, RoundRobinSelector>>,
+    content_selectors: Arc<dashmap::DashMap<String, ContentSelector>>,
+    quotas: Arc<QuotaTracker>,
 }
 
 impl RoundRobinManager {
@@ -48,6 +58,7 @@ impl RoundRobinManager {
         Self {
             url_selector: Arc::new(RoundRobinSelector::new()),
             content_selectors: Arc::new(dashmap::DashMap::new()),
+            quotas: Arc::new(QuotaTracker::new()),
         }
     }
 
@@ -55,22 +66,86 @@ impl RoundRobinManager {
         self.url_selector.select_owned(urls)
     }
 
-    pub fn select_replacement_content(&self, key: &str, content: &[String]) -> Option<String> {
+    pub fn select_replacement_content(
+        &self,
+        key: &str,
+        content: &ContentSet,
+        source: &ContentSource,
+        ctx: &RequestContext,
+    ) -> Option<ContentValue> {
         if content.is_empty() {
             return None;
         }
 
         let selector = self.content_selectors
             .entry(key.to_string())
-            .or_insert_with(RoundRobinSelector::new);
-        
-        selector.select_owned(content)
+            .or_default();
+        let quota = source.quota.as_ref();
+
+        // Only values with a quota are tracked, so other values (secrets
+        // included) are never kept or listed by the usage API.
+        if quota.is_none() {
+            return selector.select(content, source, ctx);
+        }
+
+        // Another request may take a value's last slot between selection and
+        // acquisition, so retry with the remaining values.
+        for _ in 0..content.len() {
+            let available = self.available_content(key, content, quota);
+            if available.is_empty() {
+                break;
+            }
+
+            let value = selector.select(&available, source, ctx)?;
+            if self.quotas.try_acquire(key, &value, quota) {
+                return Some(value);
+            }
+        }
+
+        warn!("All replacement values for {} are exhausted", key);
+        None
+    }
+
+    fn available_content(&self, key: &str, content: &ContentSet, quota: Option<&QuotaConfig>) -> ContentSet {
+        let mut available = ContentSet::default();
+        for (index, value) in content.values.iter().enumerate() {
+            if self.quotas.is_available(key, value, quota) {
+                available.values.push(value.clone());
+                if !content.weights.is_empty() {
+                    available.weights.push(content.weight(index));
+                }
+            }
+        }
+        available
+    }
+
+    /// Records the upstream status for a request that used `value`; returns
+    /// whether the value was marked as exhausted.
+    pub fn record_response(
+        &self,
+        key: &str,
+        value: &ContentValue,
+        quota: &QuotaConfig,
+        status: u16,
+        retry_after: Option<Duration>,
+    ) -> bool {
+        self.quotas.record_response(key, value, quota, status, retry_after)
     }
 
+    pub fn usage_snapshot(&self) -> Vec<UsageSnapshot> {
+        self.quotas.snapshot()
+    }
+
+    pub fn cleanup_quota_usage(&self, scopes: &HashMap<String, LiveScope>) -> usize {
+        self.quotas.cleanup(scopes)
+    }
+
+    #[allow(dead_code)]
     pub fn reset_url_selector(&self) {
         self.url_selector.reset();
     }
 
+    #[allow(dead_code)]
     pub fn reset_content_selector(&self, key: &str) {
         if let Some(selector) = self.content_selectors.get(key) {
             selector.reset();
@@ -119,4 +194,19 @@ mod tests {
         assert_eq!(manager.select_target_url(&urls), Some("url2".to_string()));
         assert_eq!(manager.select_target_url(&urls), Some("url1".to_string()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_only_quota_values_are_tracked() {
+        let manager = RoundRobinManager::new();
+        let content = ContentSet::new(vec!["secret".to_string()]);
+        let mut source: ContentSource = toml::from_str("source = \"inline\"").unwrap();
+        let ctx = RequestContext::default();
+
+        assert_eq!(manager.select_replacement_content("plain", &content, &source, &ctx), Some("secret".into()));
+        assert!(manager.usage_snapshot().is_empty());
+
+        source.quota = Some(toml::from_str("daily_quota = 5").unwrap());
+        manager.select_replacement_content("limited", &content, &source, &ctx);
+        assert_eq!(manager.usage_snapshot().len(), 1);
+    }
+}
@@ -1,12 +1,67 @@
-use crate::config::ForwardingRule;
+use crate::config::{ForwardingRule, ReplacementMode};
+use crate::proxy::replacement::Pattern;
+use crate::proxy::template::Template;
 use regex::Regex;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct RoutePattern {
+    #[allow(dead_code)]
     pub pattern: String,
     pub regex: Regex,
     pub rule: ForwardingRule,
+    pub compiled: Arc<CompiledRule>,
+}
+
+/// The parts of a rule that are parsed when the rule is loaded instead of
+/// on every request.
+#[derive(Debug, Default)]
+pub struct CompiledRule {
+    /// Replacement templates, by their source text.
+    templates: HashMap<String, Template>,
+    /// Body replacement patterns, by their source text and mode.
+    body_patterns: HashMap<(String, ReplacementMode), Pattern>,
+}
+
+impl CompiledRule {
+    pub fn new(rule: &ForwardingRule) -> anyhow::Result<Self> {
+        let sources = rule.header_replacements.values()
+            .chain(rule.body_replacements.values().chain(rule.response_body_replacements.values()).map(|replacement| &replacement.content))
+            .chain(rule.request_headers.iter().chain(&rule.response_headers).filter_map(|operation| operation.content.as_ref()));
+
+        let mut templates = HashMap::new();
+        for source in sources {
+            if let Some(template) = &source.template {
+                if !templates.contains_key(template) {
+                    let parsed = Template::parse(template)
+                        .map_err(|e| anyhow::anyhow!("Rule '{}': invalid template '{}': {}", rule.name, template, e))?;
+                    templates.insert(template.clone(), parsed);
+                }
+            }
+        }
+
+        let mut body_patterns = HashMap::new();
+        for (pattern, replacement) in rule.body_replacements.iter().chain(&rule.response_body_replacements) {
+            let compiled = Pattern::compile(pattern, replacement.mode)
+                .map_err(|e| anyhow::anyhow!("Rule '{}': invalid body replacement '{}': {}", rule.name, pattern, e))?;
+            body_patterns.insert((pattern.clone(), replacement.mode), compiled);
+        }
+
+        Ok(Self { templates, body_patterns })
+    }
+
+    /// The parsed form of `template`, which must belong to the rule.
+    pub fn template(&self, template: &str) -> anyhow::Result<&Template> {
+        self.templates.get(template)
+            .ok_or_else(|| anyhow::anyhow!("template '{}' was not compiled with its rule", template))
+    }
+
+    /// The compiled form of the body replacement `pattern` of the rule.
+    pub fn body_pattern(&self, pattern: &str, mode: ReplacementMode) -> anyhow::Result<&Pattern> {
+        self.body_patterns.get(&(pattern.to_string(), mode))
+            .ok_or_else(|| anyhow::anyhow!("body replacement '{}' was not compiled with its rule", pattern))
+    }
 }
 
 #[derive(Debug)]
@@ -24,16 +79,19 @@ impl ProxyRouter {
     pub fn add_rule(&mut self, rule: ForwardingRule) -> anyhow::Result<()> {
         let pattern = self.path_to_regex(&rule.path)?;
         let regex = Regex::new(&pattern)?;
+        let compiled = Arc::new(CompiledRule::new(&rule)?);
         
         self.routes.push(RoutePattern {
             pattern,
             regex,
             rule,
+            compiled,
         });
 
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn find_matching_rule(&self, path: &str) -> Option<&ForwardingRule> {
         for route in &self.routes {
             if route.regex.is_match(path) {
@@ -43,11 +101,46 @@ impl ProxyRouter {
         None
     }
 
+    /// Like `find_matching_rule`, but also returns the path captures. Wildcards
+    /// are numbered from `1`, counting only wildcards; `{name}` segments are
+    /// captured under their name.
+    pub fn find_route(&self, path: &str) -> Option<RouteMatch> {
+        for route in &self.routes {
+            if let Some(caps) = route.regex.captures(path) {
+                let mut captures = HashMap::new();
+                let mut wildcards = 0;
+                for (index, name) in route.regex.capture_names().enumerate().skip(1) {
+                    let key = match name {
+                        Some(name) => name.to_string(),
+                        None => {
+                            wildcards += 1;
+                            wildcards.to_string()
+                        }
+                    };
+                    if let Some(value) = caps.get(index) {
+                        captures.insert(key, value.as_str().to_string());
+                    }
+                }
+
+                return Some(RouteMatch {
+                    rule: route.rule.clone(),
+                    captures,
+                    compiled: route.compiled.clone(),
+                });
+            }
+        }
+        None
+    }
+
+    /// Replaces all routes. Rules are validated first, and the current routes
+    /// are kept if any rule is invalid.
     pub fn update_rules(&mut self, rules: Vec<ForwardingRule>) -> anyhow::Result<()> {
-        self.routes.clear();
+        let mut router = ProxyRouter::new();
         for rule in rules {
-            self.add_rule(rule)?;
+            rule.validate()?;
+            router.add_rule(rule)?;
         }
+        self.routes = router.routes;
         Ok(())
     }
 
@@ -65,13 +158,27 @@ impl ProxyRouter {
                 '*' => {
                     if chars.peek() == Some(&'*') {
                         chars.next();
-                        regex_pattern.push_str(".*");
+                        regex_pattern.push_str("(.*)");
                     } else {
-                        regex_pattern.push_str("[^/]*");
+                        regex_pattern.push_str("([^/]*)");
+                    }
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(anyhow::anyhow!("Unterminated path parameter '{{{}' in '{}'", name, path)),
+                        }
+                    }
+                    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                        return Err(anyhow::anyhow!("Invalid path parameter '{{{}}}' in '{}'", name, path));
                     }
+                    regex_pattern.push_str(&format!("(?P<{}>[^/]+)", name));
                 }
                 '?' => regex_pattern.push('.'),
-                '.' | '+' | '^' | '$' | '(' | ')' | '[' | ']' | '{' | '}' | '|' | '\\' => {
+                '.' | '+' | '^' | '$' | '(' | ')' | '[' | ']' | '}' | '|' | '\\' => {
                     regex_pattern.push('\\');
                     regex_pattern.push(ch);
                 }
@@ -94,6 +201,7 @@ impl Default for ProxyRouter {
 pub struct RouteMatch {
     pub rule: ForwardingRule,
     pub captures: HashMap<String, String>,
+    pub compiled: Arc<CompiledRule>,
 }
 
 #[cfg(test)]
@@ -109,6 +217,18 @@ mod tests {
             load_balancing: LoadBalancingStrategy::RoundRobin,
             header_replacements: HashMap::new(),
             body_replacements: HashMap::new(),
+            response_body_replacements: HashMap::new(),
+            replace_content_types: Vec::new(),
+            max_replacement_body_size: 1024 * 1024,
+            request_headers: Vec::new(),
+            response_headers: Vec::new(),
+            cache: None,
+            rate_limit: None,
+            concurrency: None,
+            consistent_hash: None,
+            sticky: None,
+            circuit_breaker: None,
+            upstream: None,
         }
     }
 
@@ -153,8 +273,55 @@ mod tests {
         let router = ProxyRouter::new();
         
         assert_eq!(router.path_to_regex("/api/users").unwrap(), "^/api/users$");
-        assert_eq!(router.path_to_regex("/api/*").unwrap(), "^/api/[^/]*$");
-        assert_eq!(router.path_to_regex("/api/**").unwrap(), "^/api/.*$");
+        assert_eq!(router.path_to_regex("/api/*").unwrap(), "^/api/([^/]*)$");
+        assert_eq!(router.path_to_regex("/api/**").unwrap(), "^/api/(.*)$");
+        assert_eq!(router.path_to_regex("/users/{id}").unwrap(), "^/users/(?P<id>[^/]+)$");
         assert_eq!(router.path_to_regex("/api/user?").unwrap(), "^/api/user.$");
+        assert!(router.path_to_regex("/users/{id").is_err());
+    }
+
+    #[test]
+    fn test_route_captures() {
+        let mut router = ProxyRouter::new();
+        router.add_rule(create_test_rule("test", "/v1/{tenant}/*/files/**")).unwrap();
+
+        let route = router.find_route("/v1/acme/eu/files/a/b.txt").unwrap();
+        assert_eq!(route.rule.name, "test");
+        assert_eq!(route.captures.get("tenant"), Some(&"acme".to_string()));
+        assert_eq!(route.captures.get("1"), Some(&"eu".to_string()));
+        assert_eq!(route.captures.get("2"), Some(&"a/b.txt".to_string()));
+        assert!(router.find_route("/v1/acme/other").is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_update_rules_rejects_invalid_rule() {
+        let mut router = ProxyRouter::new();
+        router.update_rules(vec![create_test_rule("test", "/api/*")]).unwrap();
+
+        let mut rule = create_test_rule("limited", "/limited");
+        rule.rate_limit = Some(crate::config::RateLimitConfig {
+            requests: 0,
+            period: 60,
+            burst: None,
+            key: Default::default(),
+        });
+        assert!(router.update_rules(vec![rule]).is_err());
+        assert_eq!(router.get_all_rules()[0].name, "test");
+    }
+
+    #[test]
+    fn test_templates_are_compiled_with_the_rule() {
+        let mut router = ProxyRouter::new();
+        let mut rule = create_test_rule("test", "/v1/{tenant}");
+        let mut source: crate::config::ContentSource = toml::from_str("source = \"inline\"").unwrap();
+        source.template = Some("{{ capture(\"tenant\") }}:{{ value }}".to_string());
+        rule.header_replacements.insert("x-key".to_string(), source);
+        router.add_rule(rule).unwrap();
+
+        let route = router.find_route("/v1/acme").unwrap();
+        let ctx = crate::proxy::template::RequestContext { captures: route.captures.clone(), ..Default::default() };
+        let template = route.compiled.template("{{ capture(\"tenant\") }}:{{ value }}").unwrap();
+        assert_eq!(template.render("k", &ctx).unwrap(), "acme:k");
+        assert!(route.compiled.template("{{ value }}").is_err());
+    }
+}
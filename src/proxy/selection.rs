@@ -0,0 +1,195 @@
+use crate::config::{ContentSource, SelectionStrategy};
+use crate::content::{ContentSet, ContentValue};
+use crate::proxy::hashing::hash64;
+use crate::proxy::template::RequestContext;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Per-replacement selection state, shared by all requests for one rule and key.
+#[derive(Debug, Default)]
+pub struct ContentSelector {
+    counter: AtomicUsize,
+    state: Mutex<SelectorState>,
+}
+
+#[derive(Debug, Default)]
+struct SelectorState {
+    /// Smooth weighted round robin running weights, parallel to the values.
+    current_weights: Vec<i64>,
+    /// Sequence number of each value's last use, for least-recently-used.
+    last_used: HashMap<ContentValue, u64>,
+    sequence: u64,
+}
+
+impl ContentSelector {
+    pub fn select(&self, content: &ContentSet, source: &ContentSource, ctx: &RequestContext) -> Option<ContentValue> {
+        if content.is_empty() {
+            return None;
+        }
+
+        let index = match source.selection {
+            SelectionStrategy::RoundRobin => self.round_robin(content),
+            SelectionStrategy::Random => rand::thread_rng().gen_range(0..content.len()),
+            SelectionStrategy::Weighted => self.weighted(content)?,
+            SelectionStrategy::StickyClientIp => match ctx.client_ip {
+                Some(ip) => sticky_index(&ip.to_string(), content.len()),
+                None => self.round_robin(content),
+            },
+            SelectionStrategy::StickyHeader => {
+                let value = source.sticky_header.as_deref()
+                    .and_then(|name| ctx.headers.get(name))
+                    .and_then(|value| value.to_str().ok());
+                match value {
+                    Some(value) => sticky_index(value, content.len()),
+                    None => self.round_robin(content),
+                }
+            }
+            SelectionStrategy::LeastRecentlyUsed => self.least_recently_used(content),
+        };
+
+        content.values.get(index).cloned()
+    }
+
+    /// Starts selection over, as if no value had been picked yet.
+    pub fn reset(&self) {
+        self.counter.store(0, Ordering::Relaxed);
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) = SelectorState::default();
+    }
+
+    fn round_robin(&self, content: &ContentSet) -> usize {
+        self.counter.fetch_add(1, Ordering::Relaxed) % content.len()
+    }
+
+    /// Smooth weighted round robin: spreads picks evenly in proportion to weight.
+    fn weighted(&self, content: &ContentSet) -> Option<usize> {
+        let total: i64 = (0..content.len()).map(|i| content.weight(i) as i64).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.current_weights.len() != content.len() {
+            state.current_weights = vec![0; content.len()];
+        }
+
+        let mut best = 0;
+        for i in 0..content.len() {
+            state.current_weights[i] += content.weight(i) as i64;
+            if state.current_weights[i] > state.current_weights[best] {
+                best = i;
+            }
+        }
+        state.current_weights[best] -= total;
+        Some(best)
+    }
+
+    fn least_recently_used(&self, content: &ContentSet) -> usize {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let index = (0..content.len())
+            .min_by_key(|&i| state.last_used.get(&content.values[i]).copied().unwrap_or(0))
+            .unwrap_or(0);
+
+        state.sequence += 1;
+        let sequence = state.sequence;
+        state.last_used.insert(content.values[index].clone(), sequence);
+
+        // Forget values that have disappeared from the source.
+        if state.last_used.len() > content.len() * 2 {
+            state.last_used.retain(|value, _| content.values.contains(value));
+        }
+
+        index
+    }
+}
+
+fn sticky_index(key: &str, len: usize) -> usize {
+    (hash64(key) % len as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn source(selection: SelectionStrategy) -> ContentSource {
+        let mut source: ContentSource = toml::from_str("source = \"inline\"\nsticky_header = \"x-user\"").unwrap();
+        source.selection = selection;
+        source
+    }
+
+    fn content(values: &[&str], weights: &[u32]) -> ContentSet {
+        ContentSet {
+            values: values.iter().map(|v| ContentValue::from(*v)).collect(),
+            weights: weights.to_vec(),
+            secret: false,
+        }
+    }
+
+    fn pick(selector: &ContentSelector, content: &ContentSet, source: &ContentSource, ctx: &RequestContext, n: usize) -> Vec<String> {
+        (0..n).map(|_| selector.select(content, source, ctx).unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let selector = ContentSelector::default();
+        let values = content(&["a", "b", "c"], &[]);
+        let picks = pick(&selector, &values, &source(SelectionStrategy::RoundRobin), &RequestContext::default(), 4);
+        assert_eq!(picks, vec!["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn test_weighted_is_smooth_and_proportional() {
+        let selector = ContentSelector::default();
+        let values = content(&["a", "b", "c"], &[5, 1, 1]);
+        let picks = pick(&selector, &values, &source(SelectionStrategy::Weighted), &RequestContext::default(), 7);
+        assert_eq!(picks, vec!["a", "a", "b", "a", "c", "a", "a"]);
+
+        let zero = content(&["a"], &[0]);
+        assert_eq!(selector.select(&zero, &source(SelectionStrategy::Weighted), &RequestContext::default()), None);
+    }
+
+    #[test]
+    fn test_sticky_selection() {
+        let selector = ContentSelector::default();
+        let values = content(&["a", "b", "c", "d", "e"], &[]);
+
+        let mut ctx = RequestContext {
+            client_ip: Some("192.168.1.20".parse().unwrap()),
+            ..Default::default()
+        };
+        let by_ip = pick(&selector, &values, &source(SelectionStrategy::StickyClientIp), &ctx, 5);
+        assert!(by_ip.iter().all(|v| v == &by_ip[0]));
+
+        ctx.headers.insert("x-user", HeaderValue::from_static("alice"));
+        let by_header = pick(&selector, &values, &source(SelectionStrategy::StickyHeader), &ctx, 5);
+        assert!(by_header.iter().all(|v| v == &by_header[0]));
+
+        // The same key maps to the same value in every process.
+        assert_eq!((by_ip[0].as_str(), by_header[0].as_str()), ("e", "c"));
+    }
+
+    #[test]
+    fn test_least_recently_used() {
+        let selector = ContentSelector::default();
+        let lru = source(SelectionStrategy::LeastRecentlyUsed);
+        let ctx = RequestContext::default();
+
+        let values = content(&["a", "b"], &[]);
+        assert_eq!(pick(&selector, &values, &lru, &ctx, 2), vec!["a", "b"]);
+
+        // A newly added value has never been used, so it goes first.
+        let values = content(&["a", "b", "c"], &[]);
+        assert_eq!(pick(&selector, &values, &lru, &ctx, 3), vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn test_random_stays_in_range() {
+        let selector = ContentSelector::default();
+        let values = content(&["a", "b"], &[]);
+        let picks = pick(&selector, &values, &source(SelectionStrategy::Random), &RequestContext::default(), 20);
+        assert!(picks.iter().all(|v| v == "a" || v == "b"));
+    }
+}
@@ -0,0 +1,398 @@
+use crate::util::iso8601;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::HeaderMap;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Request data available to replacement templates.
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    pub client_ip: Option<IpAddr>,
+    pub method: String,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub query: HashMap<String, String>,
+    pub captures: HashMap<String, String>,
+}
+
+impl RequestContext {
+    pub fn from_request(request: &Request, captures: HashMap<String, String>) -> Self {
+        let query = request.uri()
+            .query()
+            .map(|query| form_urlencoded::parse(query.as_bytes()).into_owned().collect())
+            .unwrap_or_default();
+
+        Self {
+            client_ip: request.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|info| info.0.ip()),
+            method: request.method().to_string(),
+            path: request.uri().path().to_string(),
+            headers: request.headers().clone(),
+            query,
+            captures,
+        }
+    }
+}
+
+/// A parsed replacement template. Literal text is copied as-is and each
+/// `{{ expr }}` block is evaluated, where `expr` is a string literal, a
+/// variable (`value`, `client_ip`, `method`, `path`, `timestamp`,
+/// `timestamp_ms`, `iso8601`, `uuid`) or a function call such as
+/// `header("X-User")`, `query("q")`, `capture("id")`, `env("SECRET")`,
+/// `concat(a, b, ...)`, `sha256(x)`, `hmac_sha256(key, msg)`, `hex(x)`,
+/// `base64(x)` or `base64url(x)`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Str(String),
+    Var(String),
+    Call(String, Vec<Expr>),
+}
+
+const VARIABLES: &[&str] = &[
+    "value", "client_ip", "method", "path", "timestamp", "timestamp_ms", "iso8601", "uuid",
+];
+
+impl Template {
+    pub fn parse(template: &str) -> anyhow::Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let after = &rest[start + 2..];
+            let end = expression_end(after)
+                .ok_or_else(|| anyhow::anyhow!("unclosed '{{{{' in template"))?;
+            segments.push(Segment::Expr(ExprParser::new(&after[..end]).parse()?));
+            rest = &after[end + 2..];
+        }
+
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Renders the template, with `value` bound to the selected content value.
+    pub fn render(&self, value: &str, ctx: &RequestContext) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => output.extend_from_slice(text.as_bytes()),
+                Segment::Expr(expr) => output.extend(eval(expr, value, ctx)?),
+            }
+        }
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    /// Renders the template as a regex replacement: `$1`/`${name}` in the
+    /// literal text refer to captures, while `$` in evaluated expressions is
+    /// escaped so that values are inserted verbatim.
+    pub fn render_regex_replacement(&self, value: &str, ctx: &RequestContext) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => output.extend_from_slice(text.as_bytes()),
+                Segment::Expr(expr) => {
+                    for byte in eval(expr, value, ctx)? {
+                        if byte == b'$' {
+                            output.push(b'$');
+                        }
+                        output.push(byte);
+                    }
+                }
+            }
+        }
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+}
+
+/// Offset of the `}}` closing an expression, skipping over string literals
+/// so that they may contain `}}`.
+fn expression_end(input: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut chars = input.char_indices();
+    while let Some((offset, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '}') if input[offset..].starts_with("}}") => return Some(offset),
+            (None, _) => {}
+        }
+    }
+    None
+}
+
+struct ExprParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn parse(mut self) -> anyhow::Result<Expr> {
+        let expr = self.expr()?;
+        self.skip_whitespace();
+        if self.pos != self.input.len() {
+            return Err(anyhow::anyhow!("unexpected '{}' in template expression", &self.input[self.pos..]));
+        }
+        Ok(expr)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn expr(&mut self) -> anyhow::Result<Expr> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(quote @ ('"' | '\'')) => self.string(quote),
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    self.pos += 1;
+                }
+                let name = self.input[start..self.pos].to_string();
+
+                self.skip_whitespace();
+                if self.peek() == Some('(') {
+                    self.pos += 1;
+                    let args = self.args()?;
+                    check_call(&name, args.len())?;
+                    Ok(Expr::Call(name, args))
+                } else if VARIABLES.contains(&name.as_str()) {
+                    Ok(Expr::Var(name))
+                } else {
+                    Err(anyhow::anyhow!("unknown template variable '{}'", name))
+                }
+            }
+            _ => Err(anyhow::anyhow!("expected expression in template at '{}'", &self.input[self.pos..])),
+        }
+    }
+
+    fn args(&mut self) -> anyhow::Result<Vec<Expr>> {
+        let mut args = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(')') {
+            self.pos += 1;
+            return Ok(args);
+        }
+
+        loop {
+            args.push(self.expr()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(')') => {
+                    self.pos += 1;
+                    return Ok(args);
+                }
+                _ => return Err(anyhow::anyhow!("expected ',' or ')' in template expression")),
+            }
+        }
+    }
+
+    fn string(&mut self, quote: char) -> anyhow::Result<Expr> {
+        self.pos += 1;
+        let mut value = String::new();
+        let mut chars = self.input[self.pos..].char_indices();
+
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some((_, escaped)) = chars.next() {
+                        value.push(escaped);
+                    }
+                }
+                c if c == quote => {
+                    self.pos += offset + 1;
+                    return Ok(Expr::Str(value));
+                }
+                c => value.push(c),
+            }
+        }
+
+        Err(anyhow::anyhow!("unterminated string in template expression"))
+    }
+}
+
+fn check_call(name: &str, arity: usize) -> anyhow::Result<()> {
+    let expected = match name {
+        "concat" => return Ok(()),
+        "header" | "query" | "capture" | "env" | "sha256" | "hex" | "base64" | "base64url" => 1,
+        "hmac_sha256" => 2,
+        _ => return Err(anyhow::anyhow!("unknown template function '{}'", name)),
+    };
+
+    if arity != expected {
+        return Err(anyhow::anyhow!("{}() takes {} argument(s), got {}", name, expected, arity));
+    }
+    Ok(())
+}
+
+fn eval(expr: &Expr, value: &str, ctx: &RequestContext) -> anyhow::Result<Vec<u8>> {
+    match expr {
+        Expr::Str(text) => Ok(text.as_bytes().to_vec()),
+        Expr::Var(name) => Ok(variable(name, value, ctx).into_bytes()),
+        Expr::Call(name, args) => {
+            let args = args.iter()
+                .map(|arg| eval(arg, value, ctx))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            call(name, args, ctx)
+        }
+    }
+}
+
+fn variable(name: &str, value: &str, ctx: &RequestContext) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    match name {
+        "value" => value.to_string(),
+        "client_ip" => ctx.client_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+        "method" => ctx.method.clone(),
+        "path" => ctx.path.clone(),
+        "timestamp" => now.as_secs().to_string(),
+        "timestamp_ms" => now.as_millis().to_string(),
+        "iso8601" => iso8601(now.as_secs()),
+        "uuid" => uuid::Uuid::new_v4().to_string(),
+        _ => String::new(),
+    }
+}
+
+fn call(name: &str, mut args: Vec<Vec<u8>>, ctx: &RequestContext) -> anyhow::Result<Vec<u8>> {
+    let text = |arg: &[u8]| String::from_utf8_lossy(arg).into_owned();
+
+    let result = match name {
+        "concat" => args.concat(),
+        "header" => ctx.headers
+            .get(text(&args[0]).as_str())
+            .map(|value| value.as_bytes().to_vec())
+            .unwrap_or_default(),
+        "query" => ctx.query.get(&text(&args[0])).cloned().unwrap_or_default().into_bytes(),
+        "capture" => ctx.captures.get(&text(&args[0])).cloned().unwrap_or_default().into_bytes(),
+        "env" => std::env::var(text(&args[0])).unwrap_or_default().into_bytes(),
+        "sha256" => Sha256::digest(&args[0]).to_vec(),
+        "hmac_sha256" => {
+            let message = args.pop().unwrap_or_default();
+            let mut mac = Hmac::<Sha256>::new_from_slice(&args[0])
+                .map_err(|e| anyhow::anyhow!("invalid HMAC key: {}", e))?;
+            mac.update(&message);
+            mac.finalize().into_bytes().to_vec()
+        }
+        "hex" => args[0].iter().map(|b| format!("{:02x}", b)).collect::<String>().into_bytes(),
+        "base64" => STANDARD.encode(&args[0]).into_bytes(),
+        "base64url" => URL_SAFE_NO_PAD.encode(&args[0]).into_bytes(),
+        _ => return Err(anyhow::anyhow!("unknown template function '{}'", name)),
+    };
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn context() -> RequestContext {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-user", HeaderValue::from_static("alice"));
+
+        RequestContext {
+            client_ip: Some("10.0.0.7".parse().unwrap()),
+            method: "POST".to_string(),
+            path: "/v1/acme/items".to_string(),
+            headers,
+            query: HashMap::from([("page".to_string(), "2".to_string())]),
+            captures: HashMap::from([("tenant".to_string(), "acme".to_string())]),
+        }
+    }
+
+    fn render(template: &str, value: &str, ctx: &RequestContext) -> anyhow::Result<String> {
+        Template::parse(template)?.render(value, ctx)
+    }
+
+    #[test]
+    fn test_plain_text_is_unchanged() {
+        assert_eq!(render("no templates here", "v", &context()).unwrap(), "no templates here");
+    }
+
+    #[test]
+    fn test_request_variables() {
+        let rendered = render(
+            "{{ value }}|{{client_ip}}|{{ method }}|{{ header(\"X-User\") }}|{{ query('page') }}|{{ capture(\"tenant\") }}",
+            "key-1",
+            &context(),
+        ).unwrap();
+        assert_eq!(rendered, "key-1|10.0.0.7|POST|alice|2|acme");
+    }
+
+    #[test]
+    fn test_regex_replacement_escapes_values() {
+        let template = Template::parse("$1={{ value }}").unwrap();
+        assert_eq!(template.render_regex_replacement("pa$$w0rd", &context()).unwrap(), "$1=pa$$$$w0rd");
+    }
+
+    #[test]
+    fn test_hashing_and_encoding() {
+        let ctx = context();
+        assert_eq!(render("{{ base64(\"hello\") }}", "", &ctx).unwrap(), "aGVsbG8=");
+        assert_eq!(
+            render("{{ hex(sha256(\"abc\")) }}", "", &ctx).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            render("{{ hex(hmac_sha256(\"key\", \"The quick brown fox jumps over the lazy dog\")) }}", "", &ctx).unwrap(),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        assert_eq!(
+            render("Bearer {{ base64(concat(value, \":\", method)) }}", "k", &ctx).unwrap(),
+            "Bearer azpQT1NU"
+        );
+    }
+
+    #[test]
+    fn test_braces_in_string_literals() {
+        let ctx = context();
+        assert_eq!(render("{{ concat(\"a}}b\", 'c\\'}}') }}!", "", &ctx).unwrap(), "a}}bc'}}!");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Template::parse("{{ value").is_err());
+        assert!(Template::parse("{{ unknown }}").is_err());
+        assert!(Template::parse("{{ nope(value) }}").is_err());
+        assert!(Template::parse("{{ hmac_sha256(value) }}").is_err());
+        assert!(Template::parse("{{ header(\"x) }}").is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use crate::config::{ForwardingRule, UpstreamConfig};
+use dashmap::DashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Connect timeout for rules that do not set one.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Request timeout for rules that do not set one.
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// HTTP clients for forwarding, one per rule with upstream settings so each
+/// gets its own timeouts and connection pool.
+#[derive(Debug)]
+pub struct UpstreamClients {
+    default: reqwest::Client,
+    rules: DashMap<String, Arc<RuleClient>>,
+}
+
+/// A rule's client with the settings it was built from, so that it (and its
+/// pooled connections) can be kept when the rules change but its settings do not.
+#[derive(Debug)]
+struct RuleClient {
+    config: UpstreamConfig,
+    client: reqwest::Client,
+}
+
+/// Which part of an upstream request ran out of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    Connect,
+    Request,
+    Idle,
+}
+
+impl TimeoutPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeoutPhase::Connect => "connect",
+            TimeoutPhase::Request => "request",
+            TimeoutPhase::Idle => "idle",
+        }
+    }
+}
+
+/// Returned when an upstream request hits one of the rule's timeouts.
+#[derive(Debug)]
+pub struct UpstreamTimeout {
+    pub target: String,
+    pub phase: TimeoutPhase,
+}
+
+impl std::fmt::Display for UpstreamTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} timeout talking to {}", self.phase.as_str(), self.target)
+    }
+}
+
+impl std::error::Error for UpstreamTimeout {}
+
+impl UpstreamTimeout {
+    /// Classifies a reqwest error, returning `None` if it is not a timeout.
+    pub fn from_reqwest(error: &reqwest::Error, target: &str) -> Option<Self> {
+        if !error.is_timeout() {
+            return None;
+        }
+        let phase = if error.is_connect() { TimeoutPhase::Connect } else { TimeoutPhase::Request };
+        Some(Self { target: target.to_string(), phase })
+    }
+}
+
+impl UpstreamClients {
+    pub fn new() -> Self {
+        Self {
+            default: build_client(&UpstreamConfig::default()).expect("default upstream client builds"),
+            rules: DashMap::new(),
+        }
+    }
+
+    /// Replaces the per-rule clients, rebuilding only those whose settings
+    /// changed. Nothing changes if any client fails to build.
+    pub fn update(&self, rules: &[ForwardingRule]) -> anyhow::Result<()> {
+        let mut clients = Vec::new();
+        for rule in rules {
+            if let Some(config) = &rule.upstream {
+                let current = self.rules.get(&rule.name)
+                    .filter(|current| current.config == *config)
+                    .map(|current| current.clone());
+                let client = match current {
+                    Some(current) => current,
+                    None => {
+                        let client = build_client(config)
+                            .map_err(|e| anyhow::anyhow!("Rule '{}': failed to build upstream client: {}", rule.name, e))?;
+                        Arc::new(RuleClient { config: config.clone(), client })
+                    }
+                };
+                clients.push((rule.name.clone(), client));
+            }
+        }
+
+        self.rules.retain(|name, _| clients.iter().any(|(rule, _)| rule == name));
+        for (rule, client) in clients {
+            self.rules.insert(rule, client);
+        }
+        Ok(())
+    }
+
+    /// The client for `rule`, or the shared default one.
+    pub fn get(&self, rule: &str) -> reqwest::Client {
+        self.rules.get(rule).map_or_else(|| self.default.clone(), |rule| rule.client.clone())
+    }
+}
+
+impl Default for UpstreamClients {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn build_client(config: &UpstreamConfig) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .tcp_keepalive(config.tcp_keepalive_ms.map(Duration::from_millis))
+        .connect_timeout(config.connect_timeout_ms.map_or(DEFAULT_CONNECT_TIMEOUT, Duration::from_millis))
+        .timeout(config.request_timeout_ms.map_or(DEFAULT_REQUEST_TIMEOUT, Duration::from_millis));
+
+    if let Some(max) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if let Some(ms) = config.pool_idle_timeout_ms {
+        builder = builder.pool_idle_timeout(Duration::from_millis(ms));
+    }
+    if config.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, upstream: Option<&str>) -> ForwardingRule {
+        let mut rule: ForwardingRule = toml::from_str(&format!(r#"
+            name = "{}"
+            path = "/**"
+            target_urls = ["http://127.0.0.1:1"]
+            load_balancing = "round_robin"
+        "#, name)).unwrap();
+        rule.upstream = upstream.map(|upstream| toml::from_str(upstream).unwrap());
+        rule
+    }
+
+    #[test]
+    fn test_update_keeps_unchanged_clients() {
+        let clients = UpstreamClients::new();
+        clients.update(&[rule("a", Some("connect_timeout_ms = 100")), rule("b", Some("pool_max_idle_per_host = 1"))]).unwrap();
+        let a = clients.rules.get("a").unwrap().clone();
+        let b = clients.rules.get("b").unwrap().clone();
+
+        clients.update(&[
+            rule("a", Some("connect_timeout_ms = 100")),
+            rule("b", Some("pool_max_idle_per_host = 2")),
+            rule("c", None),
+        ]).unwrap();
+        assert!(Arc::ptr_eq(&a, &clients.rules.get("a").unwrap()));
+        assert!(!Arc::ptr_eq(&b, &clients.rules.get("b").unwrap()));
+        assert!(!clients.rules.contains_key("c"));
+
+        clients.update(&[rule("b", Some("pool_max_idle_per_host = 2"))]).unwrap();
+        assert!(!clients.rules.contains_key("a"));
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        // A listener that never accepts, with its backlog filled, leaves
+        // further connection attempts unanswered.
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(1).unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut backlog = Vec::new();
+        for _ in 0..8 {
+            match tokio::time::timeout(Duration::from_millis(50), tokio::net::TcpStream::connect(address)).await {
+                Ok(stream) => backlog.push(stream),
+                Err(_) => break,
+            }
+        }
+
+        let clients = UpstreamClients::new();
+        clients.update(&[rule("slow", Some("connect_timeout_ms = 100"))]).unwrap();
+        let target = format!("http://{}", address);
+        let error = clients.get("slow").get(&target).send().await.unwrap_err();
+
+        let timeout = UpstreamTimeout::from_reqwest(&error, &target).unwrap();
+        assert_eq!(timeout.phase, TimeoutPhase::Connect);
+        assert_eq!(timeout.to_string(), format!("connect timeout talking to {}", target));
+    }
+}